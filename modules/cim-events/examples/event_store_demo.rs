@@ -1,4 +1,4 @@
-use cim_events::{Event, EventHeader, EventStore, JetStreamEventStore};
+use cim_events::{Event, EventHeader, EventStore, EventStoreExt, JetStreamEventStore};
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 use uuid::Uuid;
@@ -0,0 +1,109 @@
+//! Admin command showing how each event changed an aggregate.
+//!
+//! This is how an application mounts the diff command in its own CLI; the
+//! crate has no admin binary because only the application knows its
+//! aggregate types.
+//!
+//! Run with: cargo run --example state_diff -- diff order-1 [--from N] [--to N]
+
+use cim_events::admin::{run_diff, AdminCommand};
+use cim_events::{Event, EventSourced, EventStoreExt, InMemoryEventStore};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum OrderEvent {
+    Created { order_id: String, customer: String },
+    LineAdded { order_id: String, sku: String, quantity: u32 },
+    Paid { order_id: String, amount: f64 },
+}
+
+impl Event for OrderEvent {
+    fn event_type(&self) -> &str {
+        match self {
+            OrderEvent::Created { .. } => "OrderCreated",
+            OrderEvent::LineAdded { .. } => "LineAdded",
+            OrderEvent::Paid { .. } => "OrderPaid",
+        }
+    }
+
+    fn aggregate_id(&self) -> &str {
+        match self {
+            OrderEvent::Created { order_id, .. }
+            | OrderEvent::LineAdded { order_id, .. }
+            | OrderEvent::Paid { order_id, .. } => order_id,
+        }
+    }
+
+    fn aggregate_type(&self) -> &str {
+        "Order"
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Order {
+    id: String,
+    customer: Option<String>,
+    lines: Vec<(String, u32)>,
+    paid: Option<f64>,
+    #[serde(skip)]
+    version: u64,
+}
+
+impl EventSourced for Order {
+    type Event = OrderEvent;
+    type Error = String;
+
+    fn apply(&mut self, event: &OrderEvent) -> Result<(), String> {
+        match event {
+            OrderEvent::Created { order_id, customer } => {
+                self.id = order_id.clone();
+                self.customer = Some(customer.clone());
+            }
+            OrderEvent::LineAdded { sku, quantity, .. } => self.lines.push((sku.clone(), *quantity)),
+            OrderEvent::Paid { amount, .. } => self.paid = Some(*amount),
+        }
+        Ok(())
+    }
+
+    fn aggregate_id(&self) -> &str {
+        &self.id
+    }
+
+    fn version(&self) -> u64 {
+        self.version
+    }
+
+    fn increment_version(&mut self) {
+        self.version += 1;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    // Seed an in-memory store; a real admin tool would open the production store
+    let store = InMemoryEventStore::new();
+    let order_id = "order-1".to_string();
+
+    let events = vec![
+        OrderEvent::Created { order_id: order_id.clone(), customer: "Alice".to_string() },
+        OrderEvent::LineAdded { order_id: order_id.clone(), sku: "widget".to_string(), quantity: 2 },
+        OrderEvent::LineAdded { order_id: order_id.clone(), sku: "gadget".to_string(), quantity: 1 },
+        OrderEvent::Paid { order_id: order_id.clone(), amount: 42.0 },
+    ];
+    let mut parent = None;
+    for event in events {
+        parent = store.append_event(&order_id, event, parent).await?.cid;
+    }
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = if args.is_empty() {
+        AdminCommand::parse(["diff", order_id.as_str()])?
+    } else {
+        AdminCommand::parse(args)?
+    };
+
+    println!("{}", run_diff(&store, &command, Order::default()).await?);
+
+    Ok(())
+}
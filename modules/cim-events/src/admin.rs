@@ -0,0 +1,113 @@
+//! Operator commands that applications can expose from their own CLIs.
+//!
+//! Commands are parsed from plain arguments and render JSON, so they can be
+//! wired into any binary that knows the aggregate types involved. The crate
+//! ships no binary of its own: replaying a diff needs the application's
+//! `EventSourced` type, which only the application can supply.
+
+use std::fmt::Display;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::diff::explain_history;
+use crate::domain::EventSourced;
+use crate::event_store::{EventStore, EventStoreError, Result};
+
+/// An administrative command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// `diff <aggregate-id> [--from <sequence>] [--to <sequence>]`
+    Diff {
+        aggregate_id: String,
+        from_sequence: Option<u64>,
+        to_sequence: Option<u64>,
+    },
+}
+
+impl AdminCommand {
+    /// Parse a command from its arguments, without the program name
+    pub fn parse<I, T>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        let mut args = args.into_iter().map(Into::into);
+        let usage = || EventStoreError::AdminCommand("usage: diff <aggregate-id> [--from <sequence>] [--to <sequence>]".to_string());
+
+        match args.next().as_deref() {
+            Some("diff") => {
+                let aggregate_id = args.next().ok_or_else(usage)?;
+                let mut from_sequence = None;
+                let mut to_sequence = None;
+
+                while let Some(flag) = args.next() {
+                    let value = args
+                        .next()
+                        .and_then(|value| value.parse::<u64>().ok())
+                        .ok_or_else(usage)?;
+                    match flag.as_str() {
+                        "--from" => from_sequence = Some(value),
+                        "--to" => to_sequence = Some(value),
+                        _ => return Err(usage()),
+                    }
+                }
+
+                Ok(AdminCommand::Diff {
+                    aggregate_id,
+                    from_sequence,
+                    to_sequence,
+                })
+            }
+            Some(other) => Err(EventStoreError::AdminCommand(format!("Unknown command: {}", other))),
+            None => Err(usage()),
+        }
+    }
+}
+
+/// Run a diff command, rendering the state changes as pretty-printed JSON.
+///
+/// `initial` is the empty aggregate the history is replayed onto. The whole
+/// history is always replayed; the sequence range only limits the output.
+pub async fn run_diff<S, A>(store: &S, command: &AdminCommand, initial: A) -> Result<String>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + Serialize + DeserializeOwned + Clone + Send,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let AdminCommand::Diff {
+        aggregate_id,
+        from_sequence,
+        to_sequence,
+    } = command;
+
+    let changes: Vec<_> = explain_history(store, aggregate_id, initial)
+        .await?
+        .into_iter()
+        .filter(|change| from_sequence.is_none_or(|from| change.sequence >= from))
+        .filter(|change| to_sequence.is_none_or(|to| change.sequence <= to))
+        .collect();
+
+    Ok(serde_json::to_string_pretty(&changes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_diff_with_range() {
+        assert_eq!(
+            AdminCommand::parse(["diff", "order-1", "--from", "3", "--to", "9"]).unwrap(),
+            AdminCommand::Diff {
+                aggregate_id: "order-1".to_string(),
+                from_sequence: Some(3),
+                to_sequence: Some(9),
+            }
+        );
+        assert!(AdminCommand::parse(["diff"]).is_err());
+        assert!(AdminCommand::parse(["diff", "order-1", "--from", "x"]).is_err());
+        assert!(AdminCommand::parse(["purge", "order-1"]).is_err());
+    }
+}
@@ -0,0 +1,529 @@
+//! CAR (Content Addressable aRchive) export and import of event histories.
+//!
+//! Every event is written as a block keyed by its CID, holding the event's
+//! canonical bytes. Claim-checked payloads are included as separate blocks.
+//! The archive roots are the head CIDs of the exported chains, so a reader can
+//! walk each chain backwards through `parent_cid` links.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Cursor, Read, Write};
+
+use cid::Cid;
+
+use crate::cid_policy::verify_cid;
+use crate::content_store::ContentStore;
+use crate::event_store::{EventMetadata, EventStore, EventStoreError, Result, StoredEvent};
+
+/// Fixed pragma that starts every CARv2 file
+const CARV2_PRAGMA: [u8; 11] = [0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02];
+
+/// Size of the fixed CARv2 header following the pragma
+const CARV2_HEADER_LEN: usize = 40;
+
+/// CAR format version to write
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CarVersion {
+    V1,
+    V2,
+}
+
+/// A single block in an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarBlock {
+    pub cid: Cid,
+    pub data: Vec<u8>,
+}
+
+/// An in-memory CAR archive
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CarArchive {
+    pub roots: Vec<Cid>,
+    pub blocks: Vec<CarBlock>,
+}
+
+/// Summary of an archive import
+#[derive(Debug, Clone, Default)]
+pub struct ImportReport {
+    /// Metadata of each replayed event, in archive order
+    pub events: Vec<EventMetadata>,
+
+    /// Number of claim-checked payload blocks restored
+    pub payloads: usize,
+}
+
+impl CarArchive {
+    /// Serialize the archive in the requested format
+    pub fn write<W: Write>(&self, mut writer: W, version: CarVersion) -> Result<()> {
+        let v1 = self.to_v1_bytes();
+
+        let result = match version {
+            CarVersion::V1 => writer.write_all(&v1),
+            CarVersion::V2 => {
+                let data_offset = (CARV2_PRAGMA.len() + CARV2_HEADER_LEN) as u64;
+
+                let mut header = Vec::with_capacity(CARV2_HEADER_LEN);
+                header.extend_from_slice(&[0u8; 16]); // characteristics
+                header.extend_from_slice(&data_offset.to_le_bytes());
+                header.extend_from_slice(&(v1.len() as u64).to_le_bytes());
+                header.extend_from_slice(&0u64.to_le_bytes()); // no index
+
+                writer
+                    .write_all(&CARV2_PRAGMA)
+                    .and_then(|_| writer.write_all(&header))
+                    .and_then(|_| writer.write_all(&v1))
+            }
+        };
+
+        result.map_err(|e| EventStoreError::Car(e.to_string()))
+    }
+
+    /// Serialize the archive to bytes
+    pub fn to_bytes(&self, version: CarVersion) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes, version)?;
+        Ok(bytes)
+    }
+
+    /// Read a CARv1 or CARv2 archive
+    pub fn read<R: Read>(mut reader: R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .map_err(|e| EventStoreError::Car(e.to_string()))?;
+
+        Self::from_bytes(&bytes)
+    }
+
+    /// Parse a CARv1 or CARv2 archive from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(&CARV2_PRAGMA) {
+            let header = bytes
+                .get(CARV2_PRAGMA.len()..CARV2_PRAGMA.len() + CARV2_HEADER_LEN)
+                .ok_or_else(|| EventStoreError::Car("Truncated CARv2 header".to_string()))?;
+            let data_offset = u64::from_le_bytes(header[16..24].try_into().unwrap());
+            let data_size = u64::from_le_bytes(header[24..32].try_into().unwrap());
+
+            let data = data_offset
+                .checked_add(data_size)
+                .and_then(|end| bytes.get(usize::try_from(data_offset).ok()?..usize::try_from(end).ok()?))
+                .ok_or_else(|| EventStoreError::Car("CARv2 data payload out of bounds".to_string()))?;
+            return Self::from_v1_bytes(data);
+        }
+
+        Self::from_v1_bytes(bytes)
+    }
+
+    fn to_v1_bytes(&self) -> Vec<u8> {
+        let header = encode_header(&self.roots);
+
+        let mut out = Vec::new();
+        write_varint(&mut out, header.len() as u64);
+        out.extend_from_slice(&header);
+
+        for block in &self.blocks {
+            let cid = block.cid.to_bytes();
+            write_varint(&mut out, (cid.len() + block.data.len()) as u64);
+            out.extend_from_slice(&cid);
+            out.extend_from_slice(&block.data);
+        }
+
+        out
+    }
+
+    fn from_v1_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+
+        let header_len = read_varint(&mut cursor)?
+            .ok_or_else(|| EventStoreError::Car("Missing CAR header".to_string()))?;
+        let header = read_exact(&mut cursor, header_len)?;
+        let roots = decode_header(&header)?;
+
+        let mut blocks = Vec::new();
+        while let Some(section_len) = read_varint(&mut cursor)? {
+            let section = read_exact(&mut cursor, section_len)?;
+            let mut section = Cursor::new(section);
+
+            let cid = Cid::read_bytes(&mut section).map_err(|e| EventStoreError::Car(e.to_string()))?;
+            let offset = section.position() as usize;
+            let data = section.into_inner()[offset..].to_vec();
+
+            blocks.push(CarBlock { cid, data });
+        }
+
+        Ok(Self { roots, blocks })
+    }
+
+    /// Verify every block against its CID
+    pub fn verify(&self) -> Result<()> {
+        for block in &self.blocks {
+            verify_cid(&block.cid, &block.data)?;
+        }
+
+        let present: HashSet<&Cid> = self.blocks.iter().map(|b| &b.cid).collect();
+        if let Some(missing) = self.roots.iter().find(|root| !present.contains(root)) {
+            return Err(EventStoreError::Car(format!("Root {} is not in the archive", missing)));
+        }
+
+        Ok(())
+    }
+}
+
+/// Export the full history of one aggregate, rooted at its head CID
+pub async fn export_aggregate<S: EventStore + ?Sized>(store: &S, aggregate_id: &str) -> Result<CarArchive> {
+    let events = store.get_events(aggregate_id, 0, usize::MAX).await?;
+    archive_events(store, events).await
+}
+
+/// Export every chain a correlation touches, from the aggregate's first
+/// correlated event up to its head, rooted at each aggregate's head CID
+pub async fn export_correlation<S: EventStore + ?Sized>(
+    store: &S,
+    correlation_id: &str,
+) -> Result<CarArchive> {
+    let correlated = store.get_events_by_correlation(correlation_id).await?;
+
+    // Run each chain on to the head, so a reader walking back from a root
+    // reaches every correlated event
+    let mut events = Vec::new();
+    let mut exported = HashSet::new();
+    for event in &correlated {
+        if exported.insert(event.aggregate_id.as_str()) {
+            events.extend(store.get_events(&event.aggregate_id, event.sequence, usize::MAX).await?);
+        }
+    }
+
+    archive_events(store, events).await
+}
+
+async fn archive_events<S: EventStore + ?Sized>(store: &S, events: Vec<StoredEvent>) -> Result<CarArchive> {
+    let mut archive = CarArchive::default();
+    let mut heads: Vec<(String, Cid)> = Vec::new();
+
+    for event in &events {
+        let cid = event.verified_cid()?;
+
+        if let Some(payload_cid) = &event.payload_cid {
+            let cid = Cid::try_from(payload_cid.as_str()).map_err(|e| EventStoreError::Car(e.to_string()))?;
+            let data = serde_json::to_vec(&store.load_event_data(event).await?)?;
+            verify_cid(&cid, &data)?;
+            archive.blocks.push(CarBlock { cid, data });
+        }
+
+        archive.blocks.push(CarBlock {
+            cid,
+            data: event.canonical_bytes()?,
+        });
+
+        match heads.iter_mut().find(|(id, _)| id == &event.aggregate_id) {
+            Some(head) => head.1 = cid,
+            None => heads.push((event.aggregate_id.clone(), cid)),
+        }
+    }
+
+    archive.roots = heads.into_iter().map(|(_, cid)| cid).collect();
+    Ok(archive)
+}
+
+/// Verify an archive and replay its events into a store, preserving CIDs and headers.
+///
+/// Claim-checked payload blocks are restored into `payloads`. An archive
+/// whose events reference payloads is refused when a block is missing, or
+/// when no content store is given, since the events could not be read back.
+pub async fn import<S: EventStore + ?Sized>(
+    store: &S,
+    archive: &CarArchive,
+    payloads: Option<&dyn ContentStore>,
+) -> Result<ImportReport> {
+    archive.verify()?;
+
+    // Payload blocks are the ones referenced by events rather than events themselves
+    let mut events = Vec::new();
+    let mut blocks: HashMap<Cid, &CarBlock> = HashMap::new();
+    let mut payload_refs = HashSet::new();
+
+    for block in &archive.blocks {
+        blocks.insert(block.cid, block);
+        if let Ok(mut event) = serde_json::from_slice::<StoredEvent>(&block.data) {
+            event.cid = Some(block.cid.to_string());
+            if let Some(payload_cid) = &event.payload_cid {
+                payload_refs.insert(payload_cid.clone());
+            }
+            events.push(event);
+        }
+    }
+    events.retain(|event| !payload_refs.contains(event.cid.as_deref().unwrap_or_default()));
+
+    let mut payload_blocks = Vec::with_capacity(payload_refs.len());
+    for payload_cid in &payload_refs {
+        let cid = Cid::try_from(payload_cid.as_str()).map_err(|e| EventStoreError::Car(e.to_string()))?;
+        let block = blocks
+            .get(&cid)
+            .ok_or_else(|| EventStoreError::Car(format!("Payload {} is not in the archive", cid)))?;
+        payload_blocks.push(*block);
+    }
+
+    let mut report = ImportReport::default();
+
+    match payloads {
+        Some(content_store) => {
+            for block in payload_blocks {
+                content_store.put(&block.cid, &block.data).await?;
+                report.payloads += 1;
+            }
+        }
+        None if !payload_blocks.is_empty() => {
+            return Err(EventStoreError::Car(format!(
+                "Archive holds {} claim-checked payloads but no content store was given",
+                payload_blocks.len()
+            )));
+        }
+        None => {}
+    }
+
+    for event in events {
+        report.events.push(store.append_stored_event(event).await?);
+    }
+
+    Ok(report)
+}
+
+/// Encode the CARv1 header `{"roots": [...], "version": 1}` as DAG-CBOR
+fn encode_header(roots: &[Cid]) -> Vec<u8> {
+    let mut out = vec![0xa2]; // map(2)
+
+    write_cbor_text(&mut out, "roots");
+    write_cbor_head(&mut out, 4, roots.len() as u64);
+    for root in roots {
+        // CID links are tag 42 over the CID bytes prefixed with the identity multibase
+        out.extend_from_slice(&[0xd8, 0x2a]);
+        let mut bytes = vec![0x00];
+        bytes.extend_from_slice(&root.to_bytes());
+        write_cbor_head(&mut out, 2, bytes.len() as u64);
+        out.extend_from_slice(&bytes);
+    }
+
+    write_cbor_text(&mut out, "version");
+    write_cbor_head(&mut out, 0, 1);
+
+    out
+}
+
+/// Decode the roots from a CARv1 header
+fn decode_header(bytes: &[u8]) -> Result<Vec<Cid>> {
+    let mut cursor = Cursor::new(bytes);
+    let (major, entries) = read_cbor_head(&mut cursor)?;
+    if major != 5 {
+        return Err(EventStoreError::Car("CAR header is not a map".to_string()));
+    }
+
+    let mut roots = None;
+    let mut version = None;
+
+    for _ in 0..entries {
+        let key = read_cbor_text(&mut cursor)?;
+        match key.as_str() {
+            "roots" => {
+                let (major, count) = read_cbor_head(&mut cursor)?;
+                if major != 4 {
+                    return Err(EventStoreError::Car("CAR roots are not an array".to_string()));
+                }
+
+                let mut cids = Vec::new();
+                for _ in 0..count {
+                    let (major, tag) = read_cbor_head(&mut cursor)?;
+                    if major != 6 || tag != 42 {
+                        return Err(EventStoreError::Car("CAR root is not a CID link".to_string()));
+                    }
+                    let (major, len) = read_cbor_head(&mut cursor)?;
+                    if major != 2 {
+                        return Err(EventStoreError::Car("CAR root is not a byte string".to_string()));
+                    }
+                    let bytes = read_exact(&mut cursor, len)?;
+                    let cid = bytes
+                        .split_first()
+                        .filter(|(prefix, _)| **prefix == 0x00)
+                        .and_then(|(_, cid)| Cid::try_from(cid).ok())
+                        .ok_or_else(|| EventStoreError::Car("Invalid CID in CAR roots".to_string()))?;
+                    cids.push(cid);
+                }
+                roots = Some(cids);
+            }
+            "version" => {
+                let (major, value) = read_cbor_head(&mut cursor)?;
+                if major != 0 {
+                    return Err(EventStoreError::Car("CAR version is not an integer".to_string()));
+                }
+                version = Some(value);
+            }
+            other => {
+                return Err(EventStoreError::Car(format!("Unexpected CAR header field: {}", other)));
+            }
+        }
+    }
+
+    if version != Some(1) {
+        return Err(EventStoreError::Car(format!("Unsupported CAR version: {:?}", version)));
+    }
+
+    roots.ok_or_else(|| EventStoreError::Car("CAR header has no roots".to_string()))
+}
+
+fn write_cbor_head(out: &mut Vec<u8>, major: u8, value: u64) {
+    let major = major << 5;
+    match value {
+        0..=23 => out.push(major | value as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, value as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+}
+
+fn write_cbor_text(out: &mut Vec<u8>, text: &str) {
+    write_cbor_head(out, 3, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn read_cbor_head(cursor: &mut Cursor<&[u8]>) -> Result<(u8, u64)> {
+    let initial = read_exact(cursor, 1)?[0];
+    let major = initial >> 5;
+    let value = match initial & 0x1f {
+        small @ 0..=23 => small as u64,
+        24 => read_exact(cursor, 1)?[0] as u64,
+        25 => u16::from_be_bytes(read_exact(cursor, 2)?.try_into().unwrap()) as u64,
+        26 => u32::from_be_bytes(read_exact(cursor, 4)?.try_into().unwrap()) as u64,
+        27 => u64::from_be_bytes(read_exact(cursor, 8)?.try_into().unwrap()),
+        _ => return Err(EventStoreError::Car("Unsupported CBOR encoding in CAR header".to_string())),
+    };
+
+    Ok((major, value))
+}
+
+fn read_cbor_text(cursor: &mut Cursor<&[u8]>) -> Result<String> {
+    let (major, len) = read_cbor_head(cursor)?;
+    if major != 3 {
+        return Err(EventStoreError::Car("Expected a text key in CAR header".to_string()));
+    }
+
+    String::from_utf8(read_exact(cursor, len)?).map_err(|e| EventStoreError::Car(e.to_string()))
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Read an unsigned LEB128 varint, returning `None` at a clean end of input
+fn read_varint(cursor: &mut Cursor<&[u8]>) -> Result<Option<u64>> {
+    let mut value = 0u64;
+    let mut shift = 0;
+
+    loop {
+        let mut byte = [0u8; 1];
+        if cursor.read(&mut byte).map_err(|e| EventStoreError::Car(e.to_string()))? == 0 {
+            return if shift == 0 {
+                Ok(None)
+            } else {
+                Err(EventStoreError::Car("Truncated varint".to_string()))
+            };
+        }
+
+        if shift >= 64 {
+            return Err(EventStoreError::Car("Varint overflow".to_string()));
+        }
+
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+        shift += 7;
+    }
+}
+
+/// Read `len` bytes, checking the length against what is left before
+/// allocating, so a corrupt length cannot exhaust memory
+fn read_exact(cursor: &mut Cursor<&[u8]>, len: u64) -> Result<Vec<u8>> {
+    let remaining = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+    if len > remaining {
+        return Err(EventStoreError::Car("Unexpected end of archive".to_string()));
+    }
+
+    let mut buf = vec![0u8; len as usize];
+    cursor
+        .read_exact(&mut buf)
+        .map_err(|_| EventStoreError::Car("Unexpected end of archive".to_string()))?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(data: &[u8]) -> CarBlock {
+        use sha2::{Digest, Sha256};
+        let cid = Cid::new_v1(0x55, multihash::Multihash::wrap(0x12, &Sha256::digest(data)).unwrap());
+        CarBlock {
+            cid,
+            data: data.to_vec(),
+        }
+    }
+
+    fn archive() -> CarArchive {
+        let blocks = vec![block(b"first"), block(b"second")];
+        CarArchive {
+            roots: vec![blocks[1].cid],
+            blocks,
+        }
+    }
+
+    #[test]
+    fn archives_round_trip_in_both_versions() {
+        for version in [CarVersion::V1, CarVersion::V2] {
+            let bytes = archive().to_bytes(version).unwrap();
+            let decoded = CarArchive::from_bytes(&bytes).unwrap();
+
+            assert_eq!(decoded, archive());
+            decoded.verify().unwrap();
+        }
+    }
+
+    #[test]
+    fn verify_rejects_tampered_blocks() {
+        let mut tampered = archive();
+        tampered.blocks[0].data = b"forged".to_vec();
+
+        assert!(tampered.verify().is_err());
+    }
+
+    #[test]
+    fn malformed_lengths_are_rejected_without_allocating() {
+        let mut v2 = archive().to_bytes(CarVersion::V2).unwrap();
+        v2[CARV2_PRAGMA.len() + 16..CARV2_PRAGMA.len() + 24].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(CarArchive::from_bytes(&v2).is_err());
+
+        // A section claiming far more bytes than the archive holds
+        let mut v1 = Vec::new();
+        write_varint(&mut v1, u64::MAX / 2);
+        assert!(CarArchive::from_bytes(&v1).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_missing_roots() {
+        let mut missing = archive();
+        missing.blocks.pop();
+
+        assert!(missing.verify().is_err());
+    }
+}
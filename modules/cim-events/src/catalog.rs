@@ -0,0 +1,165 @@
+//! Enumeration of the aggregates held in a store.
+
+use serde::{Deserialize, Serialize};
+
+use crate::event_store::{EventStoreError, Result};
+
+/// Which aggregates to list, one page at a time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateQuery {
+    pub aggregate_type: Option<String>,
+    pub id_prefix: Option<String>,
+    pub after: Option<String>,
+    pub limit: usize,
+}
+
+impl Default for AggregateQuery {
+    fn default() -> Self {
+        Self {
+            aggregate_type: None,
+            id_prefix: None,
+            after: None,
+            limit: 100,
+        }
+    }
+}
+
+impl AggregateQuery {
+    /// Every aggregate, 100 per page
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only aggregates of this type
+    pub fn with_type(mut self, aggregate_type: &str) -> Self {
+        self.aggregate_type = Some(aggregate_type.to_string());
+        self
+    }
+
+    /// Only aggregates whose ID starts with this prefix
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.id_prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Continue after the cursor returned with a previous page
+    pub fn after(mut self, cursor: &str) -> Self {
+        self.after = Some(cursor.to_string());
+        self
+    }
+
+    /// Aggregates per page, at least one
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Select, order and page aggregate keys, returning the page and the
+    /// cursor for the next one
+    pub(crate) fn page(&self, mut keys: Vec<AggregateKey>) -> Result<(Vec<AggregateKey>, Option<String>)> {
+        if self.limit == 0 {
+            return Err(EventStoreError::InvalidConfig("Aggregate page limit must be at least 1".to_string()));
+        }
+        let after = self.after.as_deref().map(AggregateKey::from_cursor).transpose()?;
+
+        keys.retain(|key| {
+            self.aggregate_type.as_ref().is_none_or(|t| &key.aggregate_type == t)
+                && self.id_prefix.as_ref().is_none_or(|p| key.aggregate_id.starts_with(p.as_str()))
+                && after.as_ref().is_none_or(|after| key > after)
+        });
+        keys.sort();
+        keys.dedup();
+
+        let has_more = keys.len() > self.limit;
+        keys.truncate(self.limit);
+        let next = if has_more { keys.last().map(AggregateKey::cursor) } else { None };
+
+        Ok((keys, next))
+    }
+}
+
+/// Stream metadata of one aggregate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AggregateInfo {
+    pub aggregate_id: String,
+    pub aggregate_type: String,
+    pub first_sequence: u64,
+    pub last_sequence: u64,
+    pub event_count: u64,
+    pub head_cid: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// One page of aggregates
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AggregatePage {
+    pub aggregates: Vec<AggregateInfo>,
+
+    /// Cursor for the next page, if there is one
+    pub next: Option<String>,
+}
+
+/// Identifies an aggregate within a store; ordered by type, then ID
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct AggregateKey {
+    pub aggregate_type: String,
+    pub aggregate_id: String,
+}
+
+impl AggregateKey {
+    pub(crate) fn new(aggregate_type: &str, aggregate_id: &str) -> Self {
+        Self {
+            aggregate_type: aggregate_type.to_string(),
+            aggregate_id: aggregate_id.to_string(),
+        }
+    }
+
+    fn cursor(&self) -> String {
+        serde_json::to_string(&(&self.aggregate_type, &self.aggregate_id)).expect("string pairs always serialize")
+    }
+
+    fn from_cursor(cursor: &str) -> Result<Self> {
+        let (aggregate_type, aggregate_id): (String, String) = serde_json::from_str(cursor)
+            .map_err(|_| EventStoreError::InvalidConfig(format!("Invalid aggregate cursor: {}", cursor)))?;
+        Ok(Self {
+            aggregate_type,
+            aggregate_id,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<AggregateKey> {
+        vec![
+            AggregateKey::new("Order", "order-2"),
+            AggregateKey::new("Customer", "cust-1"),
+            AggregateKey::new("Order", "order-1"),
+            AggregateKey::new("Order", "archive-1"),
+        ]
+    }
+
+    #[test]
+    fn pages_follow_the_cursor() {
+        let query = AggregateQuery::new().with_type("Order").with_limit(2);
+
+        let (first, next) = query.page(keys()).unwrap();
+        let (second, last) = query.clone().after(&next.unwrap()).page(keys()).unwrap();
+
+        assert_eq!(first, vec![AggregateKey::new("Order", "archive-1"), AggregateKey::new("Order", "order-1")]);
+        assert_eq!(second, vec![AggregateKey::new("Order", "order-2")]);
+        assert!(last.is_none());
+    }
+
+    #[test]
+    fn prefix_filters_ids() {
+        let (page, _) = AggregateQuery::new().with_prefix("order-").page(keys()).unwrap();
+
+        assert_eq!(page.len(), 2);
+        assert!(AggregateQuery::new().after("not a cursor").page(keys()).is_err());
+        assert!(AggregateQuery::new().with_limit(0).page(keys()).is_err());
+    }
+}
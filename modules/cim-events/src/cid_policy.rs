@@ -0,0 +1,185 @@
+use cid::Cid;
+use serde::{Deserialize, Serialize};
+
+use crate::event_store::{EventStoreError, Result};
+
+/// Multihash code for SHA2-256
+pub const SHA2_256: u64 = 0x12;
+
+/// Multihash code for BLAKE3 (256-bit output)
+pub const BLAKE3: u64 = 0x1e;
+
+/// Hash function used for new CIDs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha2_256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// Multihash code of this hash function
+    pub fn code(&self) -> u64 {
+        match self {
+            HashAlgorithm::Sha2_256 => SHA2_256,
+            HashAlgorithm::Blake3 => BLAKE3,
+        }
+    }
+
+    /// Look up the hash function for a multihash code
+    pub fn from_code(code: u64) -> Result<Self> {
+        match code {
+            SHA2_256 => Ok(HashAlgorithm::Sha2_256),
+            BLAKE3 => Ok(HashAlgorithm::Blake3),
+            other => Err(EventStoreError::InvalidCidChain(format!(
+                "Unsupported multihash code 0x{:x}",
+                other
+            ))),
+        }
+    }
+
+    /// Multiformats name of this hash function, as IPFS tooling spells it
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha2_256 => "sha2-256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    /// Hash data with this function
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Sha2_256 => {
+                use sha2::{Digest, Sha256};
+                Sha256::digest(data).to_vec()
+            }
+            HashAlgorithm::Blake3 => blake3::hash(data).as_bytes().to_vec(),
+        }
+    }
+}
+
+/// Multicodec describing the content a CID points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CidCodec {
+    /// Opaque bytes
+    #[default]
+    Raw,
+
+    /// Plain JSON
+    Json,
+
+    /// DAG-JSON
+    DagJson,
+}
+
+impl CidCodec {
+    /// Multicodec code of this codec
+    pub fn code(&self) -> u64 {
+        match self {
+            CidCodec::Raw => 0x55,
+            CidCodec::Json => 0x0200,
+            CidCodec::DagJson => 0x0129,
+        }
+    }
+
+    /// Look up the codec for a multicodec code
+    pub fn from_code(code: u64) -> Result<Self> {
+        match code {
+            0x55 => Ok(CidCodec::Raw),
+            0x0200 => Ok(CidCodec::Json),
+            0x0129 => Ok(CidCodec::DagJson),
+            other => Err(EventStoreError::InvalidCidChain(format!(
+                "Unsupported multicodec code 0x{:x}",
+                other
+            ))),
+        }
+    }
+
+    /// Multiformats name of this codec, as IPFS tooling spells it
+    pub fn name(&self) -> &'static str {
+        match self {
+            CidCodec::Raw => "raw",
+            CidCodec::Json => "json",
+            CidCodec::DagJson => "dag-json",
+        }
+    }
+}
+
+/// Hash function and codec used when a store computes new CIDs.
+///
+/// Verification never consults the policy: it reads the multihash code from
+/// each CID, so chains written under different policies stay verifiable and
+/// the hash function can be changed without rewriting history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct CidPolicy {
+    pub hash: HashAlgorithm,
+    pub codec: CidCodec,
+}
+
+impl CidPolicy {
+    pub fn new(hash: HashAlgorithm, codec: CidCodec) -> Self {
+        Self { hash, codec }
+    }
+
+    /// BLAKE3 over raw bytes, as specified by the correlation design
+    pub fn blake3() -> Self {
+        Self::new(HashAlgorithm::Blake3, CidCodec::Raw)
+    }
+
+    /// Compute a CIDv1 for data under this policy
+    pub fn cid(&self, data: &[u8]) -> Cid {
+        let digest = self.hash.digest(data);
+        let hash = multihash::Multihash::wrap(self.hash.code(), &digest)
+            .expect("256-bit digests always fit in a multihash");
+
+        Cid::new_v1(self.codec.code(), hash)
+    }
+}
+
+/// Verify that data hashes to the multihash inside a CID, whichever
+/// supported hash function produced it
+pub fn verify_cid(cid: &Cid, data: &[u8]) -> Result<()> {
+    let hash = cid.hash();
+    let algorithm = HashAlgorithm::from_code(hash.code())?;
+
+    if hash.digest() != algorithm.digest(data).as_slice() {
+        return Err(EventStoreError::InvalidCidChain(format!(
+            "Content does not match CID {}",
+            cid
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_policy_matches_legacy_cids() {
+        use sha2::{Digest, Sha256};
+        let legacy = Cid::new_v1(0x55, multihash::Multihash::wrap(0x12, &Sha256::digest(b"event")).unwrap());
+
+        assert_eq!(CidPolicy::default().cid(b"event"), legacy);
+    }
+
+    #[test]
+    fn cids_from_either_hash_verify() {
+        for policy in [CidPolicy::default(), CidPolicy::blake3()] {
+            let cid = policy.cid(b"event");
+
+            assert_eq!(cid.hash().code(), policy.hash.code());
+            assert!(verify_cid(&cid, b"event").is_ok());
+            assert!(verify_cid(&cid, b"other").is_err());
+        }
+    }
+
+    #[test]
+    fn codec_is_recorded_in_cid() {
+        let cid = CidPolicy::new(HashAlgorithm::Blake3, CidCodec::DagJson).cid(b"{}");
+
+        assert_eq!(cid.codec(), 0x0129);
+        assert_eq!(CidCodec::from_code(cid.codec()).unwrap(), CidCodec::DagJson);
+    }
+}
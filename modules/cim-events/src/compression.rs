@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::event_store::{EventStoreError, Result};
+
+/// NATS header carrying the codec used for a compressed payload
+pub const CONTENT_ENCODING_HEADER: &str = "X-Content-Encoding";
+
+/// Largest payload that is compressed or decompressed.
+///
+/// Decompression stops at this size, so a small crafted message cannot
+/// expand into an unbounded allocation.
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Compression codecs supported for stored event payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    /// Zstandard at the given compression level
+    Zstd { level: i32 },
+
+    /// LZ4 block format with the uncompressed size prepended
+    Lz4,
+}
+
+impl Codec {
+    /// Zstandard at its default level
+    pub fn zstd() -> Self {
+        Codec::Zstd { level: zstd::DEFAULT_COMPRESSION_LEVEL }
+    }
+
+    /// Value written to the content encoding header
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Zstd { .. } => "zstd",
+            Codec::Lz4 => "lz4",
+        }
+    }
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Codec::Zstd { level } => zstd::bulk::compress(data, *level)
+                .map_err(|e| EventStoreError::Compression(e.to_string())),
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+        }
+    }
+}
+
+/// Decides which payloads are compressed and with which codec.
+///
+/// The policy applies to a whole store (and therefore its stream); individual
+/// event types can override the codec or opt out entirely.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionPolicy {
+    codec: Option<Codec>,
+    threshold: usize,
+    overrides: HashMap<String, Option<Codec>>,
+}
+
+impl CompressionPolicy {
+    /// Never compress payloads
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Compress payloads of at least `threshold` bytes with `codec`
+    pub fn new(codec: Codec, threshold: usize) -> Self {
+        Self {
+            codec: Some(codec),
+            threshold,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Override the codec for one event type; `None` disables compression for it
+    pub fn with_event_type(mut self, event_type: impl Into<String>, codec: Option<Codec>) -> Self {
+        self.overrides.insert(event_type.into(), codec);
+        self
+    }
+
+    /// Codec that applies to the given event type, if any
+    pub fn codec_for(&self, event_type: &str) -> Option<Codec> {
+        match self.overrides.get(event_type) {
+            Some(codec) => *codec,
+            None => self.codec,
+        }
+    }
+
+    /// Compress a payload if the policy asks for it.
+    ///
+    /// Returns the codec that was applied alongside the bytes to publish. A
+    /// payload is left untouched when it is below the threshold or when
+    /// compression would not make it smaller.
+    pub fn compress(&self, event_type: &str, data: Vec<u8>) -> Result<(Option<Codec>, Vec<u8>)> {
+        let codec = match self.codec_for(event_type) {
+            Some(codec) if data.len() >= self.threshold => codec,
+            _ => return Ok((None, data)),
+        };
+        // Readers would refuse to decompress it
+        if data.len() > MAX_DECOMPRESSED_SIZE {
+            return Err(EventStoreError::Compression(format!(
+                "Payload of {} bytes exceeds the {} byte limit",
+                data.len(),
+                MAX_DECOMPRESSED_SIZE
+            )));
+        }
+
+        let compressed = codec.compress(&data)?;
+        if compressed.len() < data.len() {
+            Ok((Some(codec), compressed))
+        } else {
+            Ok((None, data))
+        }
+    }
+}
+
+/// Undo compression according to the value of the content encoding header,
+/// refusing output larger than [`MAX_DECOMPRESSED_SIZE`]
+pub fn decompress(encoding: Option<&str>, payload: &[u8]) -> Result<Vec<u8>> {
+    match encoding {
+        None | Some("") | Some("identity") => Ok(payload.to_vec()),
+        Some("zstd") => {
+            use std::io::Read;
+
+            // Stream into a growing buffer rather than trusting a size in the frame
+            let mut data = Vec::new();
+            zstd::stream::read::Decoder::new(payload)
+                .and_then(|decoder| decoder.take(MAX_DECOMPRESSED_SIZE as u64 + 1).read_to_end(&mut data))
+                .map_err(|e| EventStoreError::Compression(e.to_string()))?;
+            if data.len() > MAX_DECOMPRESSED_SIZE {
+                return Err(too_large());
+            }
+            Ok(data)
+        }
+        Some("lz4") => {
+            // The size prefix decides the allocation, so check it first
+            let size = payload
+                .get(..4)
+                .map(|prefix| u32::from_le_bytes(prefix.try_into().expect("four byte prefix")) as usize)
+                .ok_or_else(|| EventStoreError::Compression("LZ4 payload is missing its size".to_string()))?;
+            if size > MAX_DECOMPRESSED_SIZE {
+                return Err(too_large());
+            }
+            lz4_flex::decompress_size_prepended(payload).map_err(|e| EventStoreError::Compression(e.to_string()))
+        }
+        Some(other) => Err(EventStoreError::Compression(format!(
+            "Unsupported content encoding: {}",
+            other
+        ))),
+    }
+}
+
+fn too_large() -> EventStoreError {
+    EventStoreError::Compression(format!(
+        "Decompressed payload exceeds the {} byte limit",
+        MAX_DECOMPRESSED_SIZE
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn large_payload() -> Vec<u8> {
+        "the same event data repeated ".repeat(200).into_bytes()
+    }
+
+    #[test]
+    fn small_payloads_are_not_compressed() {
+        let policy = CompressionPolicy::new(Codec::zstd(), 1024);
+        let (codec, bytes) = policy.compress("OrderCreated", b"tiny".to_vec()).unwrap();
+
+        assert!(codec.is_none());
+        assert_eq!(bytes, b"tiny");
+    }
+
+    #[test]
+    fn compressed_payloads_round_trip() {
+        for codec in [Codec::zstd(), Codec::Lz4] {
+            let policy = CompressionPolicy::new(codec, 64);
+            let (applied, bytes) = policy.compress("OrderCreated", large_payload()).unwrap();
+
+            assert_eq!(applied, Some(codec));
+            assert!(bytes.len() < large_payload().len());
+            assert_eq!(decompress(Some(codec.as_str()), &bytes).unwrap(), large_payload());
+        }
+    }
+
+    #[test]
+    fn event_type_overrides_take_precedence() {
+        let policy = CompressionPolicy::new(Codec::zstd(), 0)
+            .with_event_type("Snapshot", Some(Codec::Lz4))
+            .with_event_type("Heartbeat", None);
+
+        assert_eq!(policy.codec_for("OrderCreated"), Some(Codec::zstd()));
+        assert_eq!(policy.codec_for("Snapshot"), Some(Codec::Lz4));
+        assert_eq!(policy.codec_for("Heartbeat"), None);
+    }
+
+    #[test]
+    fn decompression_is_bounded() {
+        let oversized = vec![0u8; MAX_DECOMPRESSED_SIZE + 1];
+        let zstd = zstd::bulk::compress(&oversized, 1).unwrap();
+        let mut lz4 = ((MAX_DECOMPRESSED_SIZE + 1) as u32).to_le_bytes().to_vec();
+        lz4.extend(b"junk");
+
+        assert!(zstd.len() < 64 * 1024);
+        assert!(decompress(Some("zstd"), &zstd).is_err());
+        assert!(decompress(Some("lz4"), &lz4).is_err());
+        assert!(CompressionPolicy::new(Codec::Lz4, 0).compress("Blob", oversized).is_err());
+    }
+}
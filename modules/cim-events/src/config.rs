@@ -0,0 +1,502 @@
+use std::time::Duration;
+
+use async_nats::jetstream::{self, response::Response, stream};
+use serde::{Deserialize, Serialize};
+
+use crate::event_store::{is_stream_not_found, EventStoreError, Result};
+use crate::retention::RetentionRules;
+use crate::subject::SubjectNamespace;
+
+/// Cluster placement for the event stream
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placement {
+    /// Cluster the stream should be placed in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+
+    /// Server tags the stream's peers must carry
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl Placement {
+    /// Whether the placement leaves the choice to the server
+    fn is_unset(&self) -> bool {
+        self.cluster.as_deref().is_none_or(str::is_empty) && self.tags.is_empty()
+    }
+}
+
+/// Configuration of the JetStream stream backing an event store
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventStoreConfig {
+    stream_name: String,
+    namespace: SubjectNamespace,
+    retention: stream::RetentionPolicy,
+    storage: stream::StorageType,
+    max_age: Duration,
+    max_bytes: i64,
+    duplicate_window: Duration,
+    replicas: usize,
+    discard: stream::DiscardPolicy,
+    compression: bool,
+    placement: Option<Placement>,
+    retention_rules: Option<RetentionRules>,
+}
+
+impl EventStoreConfig {
+    /// Default settings for the named stream: file storage, limits
+    /// retention, one year of history and a two minute duplicate window
+    pub fn new(stream_name: &str) -> Self {
+        Self {
+            stream_name: stream_name.to_string(),
+            namespace: SubjectNamespace::new("events"),
+            retention: stream::RetentionPolicy::Limits,
+            storage: stream::StorageType::File,
+            max_age: Duration::from_secs(365 * 24 * 60 * 60),
+            max_bytes: -1,
+            duplicate_window: Duration::from_secs(120),
+            replicas: 1,
+            discard: stream::DiscardPolicy::Old,
+            compression: false,
+            placement: None,
+            retention_rules: None,
+        }
+    }
+
+    /// Root subject token(s) events are published under
+    pub fn with_subject_prefix(mut self, prefix: &str) -> Self {
+        let mut namespace = SubjectNamespace::new(prefix);
+        if let Some(tenant) = self.namespace.tenant() {
+            namespace = namespace.with_tenant(tenant);
+        }
+        if let Some(domain) = self.namespace.domain() {
+            namespace = namespace.with_domain(domain);
+        }
+        self.namespace = namespace;
+        self
+    }
+    
+    /// Scope all subjects to a tenant
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.namespace = self.namespace.with_tenant(tenant);
+        self
+    }
+    
+    /// Scope all subjects to a domain within the tenant
+    pub fn with_domain(mut self, domain: &str) -> Self {
+        self.namespace = self.namespace.with_domain(domain);
+        self
+    }
+
+    pub fn with_retention(mut self, retention: stream::RetentionPolicy) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    pub fn with_storage(mut self, storage: stream::StorageType) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Maximum age of events; zero keeps them forever
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Maximum stream size in bytes; -1 is unlimited
+    pub fn with_max_bytes(mut self, max_bytes: i64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    pub fn with_duplicate_window(mut self, window: Duration) -> Self {
+        self.duplicate_window = window;
+        self
+    }
+
+    /// Number of replicas in a clustered deployment (1 to 5)
+    pub fn with_replicas(mut self, replicas: usize) -> Self {
+        self.replicas = replicas;
+        self
+    }
+
+    /// What happens when a size limit is reached
+    pub fn with_discard(mut self, discard: stream::DiscardPolicy) -> Self {
+        self.discard = discard;
+        self
+    }
+
+    /// Enable server-side S2 compression of the stream
+    pub fn with_compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = Some(placement);
+        self
+    }
+
+    /// Expire events by type and category through compaction instead of
+    /// the stream's `max_age`, which is switched off
+    pub fn with_retention_rules(mut self, rules: RetentionRules) -> Self {
+        self.retention_rules = Some(rules);
+        self.max_age = Duration::ZERO;
+        self
+    }
+
+    /// Rules to pass to [`crate::retention::compact`], if any
+    pub fn retention_rules(&self) -> Option<&RetentionRules> {
+        self.retention_rules.as_ref()
+    }
+
+    pub fn stream_name(&self) -> &str {
+        &self.stream_name
+    }
+
+    pub fn subject_prefix(&self) -> &str {
+        self.namespace.prefix()
+    }
+
+    /// Subject hierarchy the store publishes and reads under
+    pub fn namespace(&self) -> &SubjectNamespace {
+        &self.namespace
+    }
+
+    /// Subjects the stream binds
+    pub fn subjects(&self) -> Vec<String> {
+        vec![self.namespace.all_events()]
+    }
+
+    /// Check the settings for values JetStream would reject or that would
+    /// silently misbehave
+    pub fn validate(&self) -> Result<()> {
+        if self.stream_name.is_empty()
+            || self
+                .stream_name
+                .contains(|c: char| c.is_whitespace() || matches!(c, '.' | '*' | '>'))
+        {
+            return Err(EventStoreError::InvalidConfig(format!(
+                "Invalid stream name: {:?}",
+                self.stream_name
+            )));
+        }
+
+        self.namespace.validate()?;
+
+        if !(1..=5).contains(&self.replicas) {
+            return Err(EventStoreError::InvalidConfig(format!(
+                "Replicas must be between 1 and 5, got {}",
+                self.replicas
+            )));
+        }
+
+        if self.max_bytes != -1 && self.max_bytes <= 0 {
+            return Err(EventStoreError::InvalidConfig(format!(
+                "Max bytes must be positive or -1 for unlimited, got {}",
+                self.max_bytes
+            )));
+        }
+
+        if self.retention_rules.is_some() && !self.max_age.is_zero() {
+            return Err(EventStoreError::InvalidConfig(
+                "A stream max age would drop events before retention rules compact them".to_string(),
+            ));
+        }
+
+        if !self.max_age.is_zero() && self.duplicate_window > self.max_age {
+            return Err(EventStoreError::InvalidConfig(
+                "Duplicate window cannot exceed the maximum age".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// JetStream stream configuration for these settings
+    pub fn stream_config(&self) -> stream::Config {
+        stream::Config {
+            name: self.stream_name.clone(),
+            subjects: self.subjects(),
+            retention: self.retention,
+            storage: self.storage,
+            max_age: self.max_age,
+            max_bytes: self.max_bytes,
+            duplicate_window: self.duplicate_window,
+            num_replicas: self.replicas,
+            discard: self.discard,
+            allow_direct: true,
+            compression: Some(if self.compression {
+                stream::Compression::S2
+            } else {
+                stream::Compression::None
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Outcome of comparing an existing stream with the desired configuration
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamChange {
+    /// The stream already matches
+    Unchanged,
+
+    /// The stream needs updating to this configuration
+    Update(Box<stream::Config>),
+}
+
+/// Work out how to bring an existing stream in line with the desired settings.
+///
+/// Storage type and retention policy cannot be changed on a live stream, so a
+/// mismatch is reported as a conflict instead of attempting an update. Subjects
+/// bound by the existing stream are kept so other producers are not cut off,
+/// unless the store is tenant-scoped: an isolated store refuses a stream that
+/// binds subjects outside its namespace.
+pub fn reconcile(existing: &stream::Config, config: &EventStoreConfig) -> Result<StreamChange> {
+    let desired = &config.stream_config();
+    
+    if config.namespace.is_isolated() {
+        if let Some(foreign) = existing
+            .subjects
+            .iter()
+            .find(|subject| !config.namespace.contains(subject))
+        {
+            return Err(EventStoreError::NamespaceViolation(format!(
+                "Stream {} binds {} outside namespace {}",
+                existing.name,
+                foreign,
+                config.namespace.root()
+            )));
+        }
+    }
+    
+    if existing.storage != desired.storage {
+        return Err(EventStoreError::StreamConfigConflict(format!(
+            "Stream {} uses {:?} storage, configured {:?}",
+            existing.name, existing.storage, desired.storage
+        )));
+    }
+
+    if existing.retention != desired.retention {
+        return Err(EventStoreError::StreamConfigConflict(format!(
+            "Stream {} uses {:?} retention, configured {:?}",
+            existing.name, existing.retention, desired.retention
+        )));
+    }
+
+    let mut merged = existing.clone();
+    for subject in &desired.subjects {
+        if !merged.subjects.contains(subject) {
+            merged.subjects.push(subject.clone());
+        }
+    }
+    merged.max_age = desired.max_age;
+    merged.max_bytes = desired.max_bytes;
+    merged.duplicate_window = desired.duplicate_window;
+    merged.num_replicas = desired.num_replicas;
+    merged.discard = desired.discard;
+    merged.allow_direct = desired.allow_direct;
+    merged.compression = desired.compression.clone();
+
+    if &merged == existing {
+        Ok(StreamChange::Unchanged)
+    } else {
+        Ok(StreamChange::Update(Box::new(merged)))
+    }
+}
+
+/// Refuse an existing stream placed other than configured.
+///
+/// Placement is only checked when configured. Moving a live stream between
+/// clusters is an operator decision, so it is reported rather than updated.
+pub fn check_placement(stream_name: &str, existing: Option<&Placement>, config: &EventStoreConfig) -> Result<()> {
+    let Some(desired) = &config.placement else {
+        return Ok(());
+    };
+    let existing = existing.filter(|placement| !placement.is_unset());
+
+    if existing != Some(desired) {
+        return Err(EventStoreError::StreamConfigConflict(format!(
+            "Stream {} is placed at {:?}, configured {:?}",
+            stream_name, existing, desired
+        )));
+    }
+
+    Ok(())
+}
+
+/// Create the stream, or reconcile an existing one with the configuration
+pub(crate) async fn ensure_stream(jetstream: &jetstream::Context, config: &EventStoreConfig) -> Result<()> {
+    config.validate()?;
+    let desired = config.stream_config();
+
+    match jetstream.get_stream(config.stream_name()).await {
+        Ok(mut stream) => {
+            let existing = stream.info().await?.config.clone();
+            if config.placement.is_some() {
+                let placement = stream_placement(jetstream, config.stream_name()).await?;
+                check_placement(config.stream_name(), placement.as_ref(), config)?;
+            }
+            if let StreamChange::Update(update) = reconcile(&existing, config)? {
+                jetstream.update_stream(*update).await?;
+            }
+        }
+        Err(e) if is_stream_not_found(&e) => match &config.placement {
+            Some(placement) => create_placed_stream(jetstream, &desired, placement).await?,
+            None => {
+                jetstream.create_stream(desired).await?;
+            }
+        },
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+/// The part of a `STREAM.INFO` reply carrying placement, which the client's
+/// stream configuration does not model
+#[derive(Deserialize)]
+struct PlacementInfo {
+    config: PlacementConfig,
+}
+
+#[derive(Deserialize)]
+struct PlacementConfig {
+    #[serde(default)]
+    placement: Option<Placement>,
+}
+
+async fn stream_placement(jetstream: &jetstream::Context, stream_name: &str) -> Result<Option<Placement>> {
+    let response: Response<PlacementInfo> = jetstream
+        .request(format!("STREAM.INFO.{}", stream_name), &serde_json::json!({}))
+        .await?;
+
+    match response {
+        Response::Ok(info) => Ok(info.config.placement),
+        Response::Err { error } => Err(error.into()),
+    }
+}
+
+/// Stream creation request carrying placement, which the client's
+/// stream configuration does not model
+#[derive(Serialize)]
+struct PlacedStreamConfig<'a> {
+    #[serde(flatten)]
+    config: &'a stream::Config,
+    placement: &'a Placement,
+}
+
+async fn create_placed_stream(
+    jetstream: &jetstream::Context,
+    config: &stream::Config,
+    placement: &Placement,
+) -> Result<()> {
+    let request = PlacedStreamConfig { config, placement };
+    let response: Response<stream::Info> = jetstream
+        .request(format!("STREAM.CREATE.{}", config.name), &request)
+        .await?;
+
+    match response {
+        Response::Ok(_) => Ok(()),
+        Response::Err { error } => Err(EventStoreError::StreamConfigConflict(error.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_matches_previous_stream_settings() {
+        let config = EventStoreConfig::new("orders").stream_config();
+
+        assert_eq!(config.subjects, vec!["events.>".to_string()]);
+        assert_eq!(config.max_age, Duration::from_secs(365 * 24 * 60 * 60));
+        assert_eq!(config.duplicate_window, Duration::from_secs(120));
+        assert_eq!(config.storage, stream::StorageType::File);
+    }
+
+    #[test]
+    fn validate_rejects_bad_settings() {
+        assert!(EventStoreConfig::new("orders").validate().is_ok());
+        assert!(EventStoreConfig::new("bad.name").validate().is_err());
+        assert!(EventStoreConfig::new("orders").with_subject_prefix("a.*").validate().is_err());
+        assert!(EventStoreConfig::new("orders").with_replicas(0).validate().is_err());
+        assert!(EventStoreConfig::new("orders").with_max_bytes(0).validate().is_err());
+        assert!(EventStoreConfig::new("orders")
+            .with_max_age(Duration::from_secs(60))
+            .validate()
+            .is_err());
+        assert!(EventStoreConfig::new("orders")
+            .with_retention_rules(RetentionRules::new())
+            .with_max_age(Duration::from_secs(86400))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn reconcile_refuses_immutable_changes() {
+        let existing = EventStoreConfig::new("orders").stream_config();
+        let desired = EventStoreConfig::new("orders").with_storage(stream::StorageType::Memory);
+
+        assert!(matches!(
+            reconcile(&existing, &desired),
+            Err(EventStoreError::StreamConfigConflict(_))
+        ));
+    }
+
+    #[test]
+    fn reconcile_keeps_existing_subjects_and_skips_no_ops() {
+        let mut existing = EventStoreConfig::new("orders").stream_config();
+        existing.subjects.push("legacy.>".to_string());
+
+        assert_eq!(
+            reconcile(&existing, &EventStoreConfig::new("orders")).unwrap(),
+            StreamChange::Unchanged
+        );
+
+        let desired = EventStoreConfig::new("orders").with_replicas(3);
+        match reconcile(&existing, &desired).unwrap() {
+            StreamChange::Update(update) => {
+                assert_eq!(update.num_replicas, 3);
+                assert!(update.subjects.contains(&"legacy.>".to_string()));
+            }
+            StreamChange::Unchanged => panic!("replica change should update the stream"),
+        }
+    }
+
+    #[test]
+    fn tenant_stores_refuse_shared_streams() {
+        let shared = EventStoreConfig::new("orders").stream_config();
+        let tenant = EventStoreConfig::new("orders").with_tenant("acme").with_domain("sales");
+
+        assert_eq!(tenant.subjects(), vec!["acme.sales.events.>".to_string()]);
+        assert!(matches!(
+            reconcile(&shared, &tenant),
+            Err(EventStoreError::NamespaceViolation(_))
+        ));
+    }
+
+    #[test]
+    fn placement_must_match_when_configured() {
+        let east = Placement {
+            cluster: Some("east".to_string()),
+            tags: Vec::new(),
+        };
+        let placed = EventStoreConfig::new("orders").with_placement(east.clone());
+
+        assert!(check_placement("orders", Some(&east), &placed).is_ok());
+        assert!(check_placement("orders", Some(&Placement::default()), &EventStoreConfig::new("orders")).is_ok());
+        assert!(matches!(
+            check_placement("orders", None, &placed),
+            Err(EventStoreError::StreamConfigConflict(_))
+        ));
+        let west = Placement {
+            cluster: Some("west".to_string()),
+            tags: Vec::new(),
+        };
+        assert!(check_placement("orders", Some(&west), &placed).is_err());
+    }
+}
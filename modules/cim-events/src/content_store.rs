@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_nats::jetstream::object_store::{GetErrorKind, InfoErrorKind, ObjectStore};
+use async_trait::async_trait;
+use cid::Cid;
+use tokio::io::AsyncReadExt;
+use tokio::sync::RwLock;
+
+use crate::cid_policy::verify_cid;
+use crate::event_store::{is_stream_not_found, EventStoreError, Result};
+
+/// Content-addressed blob storage keyed by CID
+#[async_trait]
+pub trait ContentStore: Send + Sync {
+    /// Store bytes under the given CID, refusing bytes that do not hash to it
+    async fn put(&self, cid: &Cid, data: &[u8]) -> Result<()>;
+
+    /// Fetch the bytes for a CID, verifying them against the CID
+    async fn get(&self, cid: &Cid) -> Result<Vec<u8>>;
+
+    /// Check whether the store holds a CID
+    async fn has(&self, cid: &Cid) -> Result<bool>;
+}
+
+/// In-memory content store for testing and development
+#[derive(Clone, Default)]
+pub struct InMemoryContentStore {
+    blocks: Arc<RwLock<HashMap<Cid, Vec<u8>>>>,
+}
+
+impl InMemoryContentStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl ContentStore for InMemoryContentStore {
+    async fn put(&self, cid: &Cid, data: &[u8]) -> Result<()> {
+        verify_cid(cid, data)?;
+
+        let mut blocks = self.blocks.write().await;
+        blocks.entry(*cid).or_insert_with(|| data.to_vec());
+        Ok(())
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Vec<u8>> {
+        let blocks = self.blocks.read().await;
+        let data = blocks
+            .get(cid)
+            .cloned()
+            .ok_or_else(|| EventStoreError::EventNotFound(cid.to_string()))?;
+
+        verify_cid(cid, &data)?;
+        Ok(data)
+    }
+
+    async fn has(&self, cid: &Cid) -> Result<bool> {
+        let blocks = self.blocks.read().await;
+        Ok(blocks.contains_key(cid))
+    }
+}
+
+/// Local filesystem blockstore.
+///
+/// Blocks are sharded into directories named after the next-to-last two
+/// characters of the CID (the same layout as a go-ipfs flatfs datastore), which
+/// keeps directory sizes bounded for large stores.
+#[derive(Debug, Clone)]
+pub struct FileSystemContentStore {
+    root: PathBuf,
+}
+
+impl FileSystemContentStore {
+    /// Open or create a blockstore rooted at the given directory
+    pub async fn new(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&root)
+            .await
+            .map_err(|e| EventStoreError::ContentStore(e.to_string()))?;
+
+        Ok(Self { root })
+    }
+
+    fn block_path(&self, cid: &Cid) -> PathBuf {
+        let key = cid.to_string();
+        let shard = if key.len() >= 3 {
+            &key[key.len() - 3..key.len() - 1]
+        } else {
+            "_"
+        };
+
+        self.root.join(shard).join(format!("{}.data", key))
+    }
+}
+
+#[async_trait]
+impl ContentStore for FileSystemContentStore {
+    async fn put(&self, cid: &Cid, data: &[u8]) -> Result<()> {
+        verify_cid(cid, data)?;
+
+        let path = self.block_path(cid);
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(());
+        }
+
+        let dir = path.parent().expect("block paths always have a shard directory");
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| EventStoreError::ContentStore(e.to_string()))?;
+
+        // Write to a temporary file first so readers never observe partial blocks
+        let tmp = dir.join(format!("{}.tmp-{}", cid, uuid::Uuid::new_v4()));
+        tokio::fs::write(&tmp, data)
+            .await
+            .map_err(|e| EventStoreError::ContentStore(e.to_string()))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| EventStoreError::ContentStore(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Vec<u8>> {
+        let data = match tokio::fs::read(self.block_path(cid)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(EventStoreError::EventNotFound(cid.to_string()))
+            }
+            Err(e) => return Err(EventStoreError::ContentStore(e.to_string())),
+        };
+
+        verify_cid(cid, &data)?;
+        Ok(data)
+    }
+
+    async fn has(&self, cid: &Cid) -> Result<bool> {
+        Ok(tokio::fs::try_exists(self.block_path(cid)).await.unwrap_or(false))
+    }
+}
+
+/// Content store backed by a NATS JetStream object store bucket
+#[derive(Clone)]
+pub struct ObjectStoreContentStore {
+    bucket: ObjectStore,
+}
+
+impl ObjectStoreContentStore {
+    /// Use an existing object store bucket
+    pub fn new(bucket: ObjectStore) -> Self {
+        Self { bucket }
+    }
+
+    /// Get or create the named bucket on the given JetStream context
+    pub async fn open(jetstream: &async_nats::jetstream::Context, bucket: &str) -> Result<Self> {
+        // Look the bucket's stream up first: a failed bucket lookup does not
+        // say whether the bucket is missing or the lookup itself failed
+        let bucket = match jetstream.get_stream(format!("OBJ_{}", bucket)).await {
+            Ok(_) => jetstream
+                .get_object_store(bucket)
+                .await
+                .map_err(|e| EventStoreError::ContentStore(e.to_string()))?,
+            Err(e) if is_stream_not_found(&e) => jetstream
+                .create_object_store(async_nats::jetstream::object_store::Config {
+                    bucket: bucket.to_string(),
+                    ..Default::default()
+                })
+                .await
+                .map_err(|e| EventStoreError::ContentStore(e.to_string()))?,
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self::new(bucket))
+    }
+}
+
+#[async_trait]
+impl ContentStore for ObjectStoreContentStore {
+    async fn put(&self, cid: &Cid, data: &[u8]) -> Result<()> {
+        verify_cid(cid, data)?;
+        if self.has(cid).await? {
+            return Ok(());
+        }
+
+        let mut reader = data;
+        self.bucket
+            .put(cid.to_string().as_str(), &mut reader)
+            .await
+            .map_err(|e| EventStoreError::ContentStore(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Vec<u8>> {
+        let mut object = self
+            .bucket
+            .get(cid.to_string())
+            .await
+            .map_err(|e| match e.kind() {
+                GetErrorKind::NotFound => EventStoreError::EventNotFound(cid.to_string()),
+                _ => e.into(),
+            })?;
+
+        let mut data = Vec::new();
+        object
+            .read_to_end(&mut data)
+            .await
+            .map_err(|e| EventStoreError::ContentStore(e.to_string()))?;
+
+        verify_cid(cid, &data)?;
+        Ok(data)
+    }
+
+    async fn has(&self, cid: &Cid) -> Result<bool> {
+        match self.bucket.info(cid.to_string()).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == InfoErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Content store backed by an IPFS node's HTTP block API
+#[cfg(feature = "ipfs")]
+#[derive(Clone)]
+pub struct IpfsContentStore {
+    client: Arc<ipfs_api::IpfsClient>,
+}
+
+#[cfg(feature = "ipfs")]
+impl IpfsContentStore {
+    pub fn new(client: ipfs_api::IpfsClient) -> Self {
+        Self {
+            client: Arc::new(client),
+        }
+    }
+}
+
+#[cfg(feature = "ipfs")]
+#[async_trait]
+impl ContentStore for IpfsContentStore {
+    async fn put(&self, cid: &Cid, data: &[u8]) -> Result<()> {
+        use crate::cid_policy::{CidCodec, HashAlgorithm};
+        use ipfs_api::IpfsApi;
+
+        verify_cid(cid, data)?;
+
+        // Store the block under the CID's own hash function and codec, so
+        // the node files it under the key `get` will ask for
+        let hash = HashAlgorithm::from_code(cid.hash().code())?;
+        let codec = CidCodec::from_code(cid.codec())?;
+        let options = ipfs_api::request::BlockPut {
+            mhtype: Some(hash.name()),
+            cid_codec: Some(codec.name()),
+            ..Default::default()
+        };
+
+        let response = self
+            .client
+            .block_put_with_options(std::io::Cursor::new(data.to_vec()), options)
+            .await
+            .map_err(|e| EventStoreError::Ipfs(e.to_string()))?;
+
+        let stored = Cid::try_from(response.key.as_str()).map_err(|e| EventStoreError::Ipfs(e.to_string()))?;
+        if stored != *cid {
+            return Err(EventStoreError::Ipfs(format!("Block for {} was stored as {}", cid, stored)));
+        }
+
+        Ok(())
+    }
+
+    async fn get(&self, cid: &Cid) -> Result<Vec<u8>> {
+        use futures::TryStreamExt;
+        use ipfs_api::IpfsApi;
+
+        let data = self
+            .client
+            .block_get(&cid.to_string())
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .map_err(|e| EventStoreError::Ipfs(e.to_string()))?;
+
+        verify_cid(cid, &data)?;
+        Ok(data)
+    }
+
+    async fn has(&self, cid: &Cid) -> Result<bool> {
+        use ipfs_api::IpfsApi;
+
+        Ok(self.client.block_stat(&cid.to_string()).await.is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256_cid(data: &[u8]) -> Cid {
+        use sha2::{Digest, Sha256};
+        Cid::new_v1(0x55, multihash::Multihash::wrap(0x12, &Sha256::digest(data)).unwrap())
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_round_trips_blocks() {
+        let store = InMemoryContentStore::new();
+        let cid = sha256_cid(b"payload");
+
+        store.put(&cid, b"payload").await.unwrap();
+
+        assert!(store.has(&cid).await.unwrap());
+        assert_eq!(store.get(&cid).await.unwrap(), b"payload");
+        assert!(store.get(&sha256_cid(b"missing")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn in_memory_store_rejects_blocks_that_do_not_match_their_cid() {
+        let store = InMemoryContentStore::new();
+        let cid = sha256_cid(b"payload");
+
+        assert!(matches!(store.put(&cid, b"tampered").await, Err(EventStoreError::InvalidCidChain(_))));
+        assert!(!store.has(&cid).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn filesystem_store_round_trips_blocks() {
+        let root = std::env::temp_dir().join(format!("cim-blocks-{}", uuid::Uuid::new_v4()));
+        let store = FileSystemContentStore::new(&root).await.unwrap();
+        let cid = sha256_cid(b"payload");
+
+        assert!(!store.has(&cid).await.unwrap());
+        store.put(&cid, b"payload").await.unwrap();
+
+        assert!(store.has(&cid).await.unwrap());
+        assert_eq!(store.get(&cid).await.unwrap(), b"payload");
+
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
+/// Aggregate type used for events that do not declare one
+pub const DEFAULT_AGGREGATE_TYPE: &str = "aggregate";
+
 /// Core trait for all events in the system
 pub trait Event: Debug + Clone + Send + Sync {
     /// Get the type of this event
@@ -8,6 +11,11 @@ pub trait Event: Debug + Clone + Send + Sync {
     
     /// Get the aggregate ID this event belongs to
     fn aggregate_id(&self) -> &str;
+    
+    /// Get the type (category) of the aggregate this event belongs to
+    fn aggregate_type(&self) -> &str {
+        DEFAULT_AGGREGATE_TYPE
+    }
 }
 
 /// Standard event header with correlation and causation tracking
@@ -62,6 +70,20 @@ impl EventHeader {
             timestamp: chrono::Utc::now(),
         }
     }
+    
+    /// Use a caller-supplied message ID, such as an idempotency key.
+    ///
+    /// Appends carrying the same message ID are stored only once.
+    pub fn with_message_id(mut self, message_id: impl Into<String>) -> Self {
+        self.message_id = message_id.into();
+        self
+    }
+}
+
+impl Default for EventHeader {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// Event envelope that wraps any event with metadata
@@ -143,6 +165,18 @@ pub enum ExpectedVersion {
     Exact(u64),
 }
 
+impl ExpectedVersion {
+    /// Whether an aggregate at `current` version satisfies the expectation;
+    /// an aggregate without events is at version 0
+    pub fn matches(&self, current: u64) -> bool {
+        match self {
+            ExpectedVersion::Any => true,
+            ExpectedVersion::NoStream => current == 0,
+            ExpectedVersion::Exact(version) => current == *version,
+        }
+    }
+}
+
 /// Result of a command execution
 #[derive(Debug)]
 pub struct CommandResult<E> {
@@ -204,4 +238,13 @@ mod tests {
         assert_eq!(envelope.event.data, event.data);
         assert!(!envelope.header.message_id.is_empty());
     }
+    
+    #[test]
+    fn expected_version_matches_current_version() {
+        assert!(ExpectedVersion::Any.matches(7));
+        assert!(ExpectedVersion::NoStream.matches(0));
+        assert!(!ExpectedVersion::NoStream.matches(1));
+        assert!(ExpectedVersion::Exact(3).matches(3));
+        assert!(!ExpectedVersion::Exact(3).matches(4));
+    }
 }
\ No newline at end of file
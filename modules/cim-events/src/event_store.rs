@@ -1,17 +1,22 @@
-use async_nats::jetstream::{self, consumer, stream};
+use async_nats::jetstream::{self, consumer, context::PublishErrorKind, response::Response, stream::DirectGetErrorKind};
 use async_trait::async_trait;
 use cid::Cid;
-use ipfs_api::{IpfsApi, IpfsClient};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::sync::Arc;
 use thiserror::Error;
-use tokio::sync::RwLock;
-use uuid::Uuid;
 
 // Import cim-subject for proper NATS subject handling
-use cim_subject::{Subject, SubjectBuilder, MessageIdentity};
+use cim_subject::Subject;
 
-use crate::domain::{Event, EventHeader};
+use crate::catalog::{AggregateInfo, AggregateKey, AggregatePage, AggregateQuery};
+use crate::cid_policy::CidPolicy;
+use crate::compression::{self, CompressionPolicy, CONTENT_ENCODING_HEADER};
+use crate::config::{self, EventStoreConfig};
+use crate::subject::{encode_token, SubjectNamespace};
+use crate::content_store::ContentStore;
+use crate::domain::{Event, EventHeader, DEFAULT_AGGREGATE_TYPE};
+use crate::lifecycle::{ensure_open, Checkpoint};
 
 #[derive(Error, Debug)]
 pub enum EventStoreError {
@@ -35,16 +40,159 @@ pub enum EventStoreError {
     
     #[error("Concurrent modification detected")]
     ConcurrentModification,
+    
+    #[error("Compression error: {0}")]
+    Compression(String),
+    
+    #[error("Content store error: {0}")]
+    ContentStore(String),
+    
+    #[error("CAR archive error: {0}")]
+    Car(String),
+    
+    #[error("Invalid configuration: {0}")]
+    InvalidConfig(String),
+    
+    #[error("Stream configuration conflict: {0}")]
+    StreamConfigConflict(String),
+    
+    #[error("Namespace violation: {0}")]
+    NamespaceViolation(String),
+    
+    #[error("Invalid subject token: {0}")]
+    InvalidSubjectToken(String),
+    
+    #[error("Rehydration error: {0}")]
+    Rehydration(String),
+    
+    #[error("Admin command error: {0}")]
+    AdminCommand(String),
+    
+    #[error("Aggregate is closed: {0}")]
+    AggregateClosed(String),
+    
+    #[error("Aggregate type conflict: {0}")]
+    AggregateTypeConflict(String),
+    
+    #[error("Event store unavailable: {0}")]
+    Unavailable(String),
+    
+    #[error("Write-ahead log error: {0}")]
+    Wal(String),
+    
+    #[error("Storage error: {0}")]
+    Storage(String),
+    
+    #[error("Database error: {0}")]
+    Database(String),
+}
+
+/// Typed client errors, such as failed stream lookups or publishes, are kept
+/// whole so callers can still inspect their kind
+impl<K> From<async_nats::error::Error<K>> for EventStoreError
+where
+    K: Clone + std::fmt::Debug + std::fmt::Display + PartialEq + Send + Sync + 'static,
+{
+    fn from(e: async_nats::error::Error<K>) -> Self {
+        EventStoreError::Nats(Box::new(e))
+    }
+}
+
+/// Whether a stream lookup failed because the stream does not exist, as
+/// opposed to the lookup itself failing
+pub(crate) fn is_stream_not_found(error: &jetstream::context::GetStreamError) -> bool {
+    matches!(
+        error.kind(),
+        jetstream::context::GetStreamErrorKind::JetStream(e) if e.error_code() == jetstream::ErrorCode::STREAM_NOT_FOUND
+    )
+}
+
+impl EventStoreError {
+    /// Whether the server could not be reached or did not answer in time,
+    /// so the same call may succeed if retried.
+    ///
+    /// Timeouts, missing responders, broken connections and an unavailable
+    /// JetStream cluster count; errors the server returned for the request
+    /// itself do not.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            EventStoreError::Nats(e) => is_transient_client_error(e.as_ref()),
+            EventStoreError::JetStream(e) => e.error_code() == jetstream::ErrorCode::CLUSTER_NOT_AVAILABLE,
+            _ => false,
+        }
+    }
+}
+
+/// Whether a client error means the request never got an answer
+fn is_transient_client_error(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    use async_nats::jetstream::{consumer::{self, pull}, context, stream};
+    
+    if let Some(e) = e.downcast_ref::<context::PublishError>() {
+        return matches!(e.kind(), context::PublishErrorKind::TimedOut | context::PublishErrorKind::BrokenPipe);
+    }
+    if let Some(e) = e.downcast_ref::<context::RequestError>() {
+        return matches!(e.kind(), context::RequestErrorKind::TimedOut | context::RequestErrorKind::NoResponders);
+    }
+    if let Some(e) = e.downcast_ref::<async_nats::RequestError>() {
+        return matches!(e.kind(), async_nats::RequestErrorKind::TimedOut | async_nats::RequestErrorKind::NoResponders);
+    }
+    // The request kinds wrap a failed API request, as opposed to an error reply
+    if let Some(e) = e.downcast_ref::<context::GetStreamError>() {
+        return e.kind() == context::GetStreamErrorKind::Request;
+    }
+    if let Some(e) = e.downcast_ref::<context::CreateStreamError>() {
+        return matches!(
+            e.kind(),
+            context::CreateStreamErrorKind::TimedOut | context::CreateStreamErrorKind::JetStreamUnavailable
+        );
+    }
+    if let Some(e) = e.downcast_ref::<stream::DirectGetError>() {
+        return e.kind() == DirectGetErrorKind::TimedOut;
+    }
+    if let Some(e) = e.downcast_ref::<stream::ConsumerError>() {
+        return matches!(e.kind(), stream::ConsumerErrorKind::TimedOut | stream::ConsumerErrorKind::Request);
+    }
+    if let Some(e) = e.downcast_ref::<stream::PurgeError>() {
+        return matches!(e.kind(), stream::PurgeErrorKind::TimedOut | stream::PurgeErrorKind::Request);
+    }
+    if let Some(e) = e.downcast_ref::<consumer::StreamError>() {
+        return e.kind() == consumer::StreamErrorKind::TimedOut;
+    }
+    // Idle heartbeats stop arriving when the connection drops
+    if let Some(e) = e.downcast_ref::<pull::OrderedError>() {
+        return e.kind() == pull::OrderedErrorKind::MissingHeartbeat;
+    }
+    if let Some(e) = e.downcast_ref::<pull::MessagesError>() {
+        return e.kind() == pull::MessagesErrorKind::MissingHeartbeat;
+    }
+    if let Some(e) = e.downcast_ref::<std::io::Error>() {
+        return matches!(
+            e.kind(),
+            std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::BrokenPipe
+        );
+    }
+    
+    false
 }
 
 pub type Result<T> = std::result::Result<T, EventStoreError>;
 
+/// Lazily read events, in store order
+pub type ReadStream = Pin<Box<dyn Stream<Item = Result<StoredEvent>> + Send>>;
+
 /// Metadata returned after storing an event
 #[derive(Debug, Clone)]
 pub struct EventMetadata {
     pub sequence: u64,
     pub cid: Option<Cid>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    /// The message ID had already been stored; nothing new was written
+    pub duplicate: bool,
 }
 
 /// A stored event with full metadata
@@ -52,363 +200,1316 @@ pub struct EventMetadata {
 pub struct StoredEvent {
     pub sequence: u64,
     pub aggregate_id: String,
+    /// Type of the aggregate, used as the event's category
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregate_type: Option<String>,
     pub event_type: String,
     pub event_data: serde_json::Value,
+    /// CID of the payload when it was claim-checked into a content store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_cid: Option<String>,
     pub header: EventHeader,
     pub cid: Option<String>,
     pub parent_cid: Option<String>,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
-/// Event store trait for appending and retrieving events
+impl StoredEvent {
+    /// Canonical bytes the event CID is computed over.
+    ///
+    /// This is the uncompressed JSON form with the store-assigned sequence and
+    /// the CID itself cleared, so transport concerns never affect integrity.
+    pub fn canonical_bytes(&self) -> Result<Vec<u8>> {
+        let mut canonical = self.clone();
+        canonical.sequence = 0;
+        canonical.cid = None;
+        Ok(serde_json::to_vec(&canonical)?)
+    }
+    
+    /// Verify the event's CID against its canonical bytes and return it
+    pub fn verified_cid(&self) -> Result<Cid> {
+        let cid_str = self.cid.as_deref().ok_or_else(|| {
+            EventStoreError::InvalidCidChain(format!(
+                "Event {} has no CID",
+                self.header.message_id
+            ))
+        })?;
+        let cid = Cid::try_from(cid_str)
+            .map_err(|e| EventStoreError::InvalidCidChain(e.to_string()))?;
+        
+        crate::cid_policy::verify_cid(&cid, &self.canonical_bytes()?)?;
+        Ok(cid)
+    }
+    
+    /// Parse the parent CID, if any
+    pub fn parent_cid_value(&self) -> Result<Option<Cid>> {
+        self.parent_cid
+            .as_deref()
+            .map(|parent| {
+                Cid::try_from(parent).map_err(|e| EventStoreError::InvalidCidChain(e.to_string()))
+            })
+            .transpose()
+    }
+    
+    /// Category the event is published under
+    pub fn category(&self) -> &str {
+        self.aggregate_type.as_deref().unwrap_or(DEFAULT_AGGREGATE_TYPE)
+    }
+    
+    /// Whether the payload lives in a content store rather than inline
+    pub fn is_claim_checked(&self) -> bool {
+        self.payload_cid.is_some()
+    }
+}
+
+/// Which events a query covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EventScope {
+    /// Every event in the store
+    All,
+    
+    /// Events of one aggregate
+    Aggregate(String),
+    
+    /// Events of every aggregate of one type
+    Category(String),
+}
+
+impl EventScope {
+    /// Whether an event falls inside the scope
+    pub fn matches(&self, event: &StoredEvent) -> bool {
+        match self {
+            EventScope::All => true,
+            EventScope::Aggregate(aggregate_id) => &event.aggregate_id == aggregate_id,
+            EventScope::Category(aggregate_type) => event.category() == aggregate_type,
+        }
+    }
+}
+
+/// Where a subscription starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscribeFrom {
+    /// Every stored event, then new ones
+    Beginning,
+    
+    /// Stored events from this sequence (inclusive), then new ones
+    Sequence(u64),
+    
+    /// Events stored at or after this time, then new ones
+    Timestamp(chrono::DateTime<chrono::Utc>),
+    
+    /// The latest stored event, then new ones
+    Last,
+    
+    /// Only events appended after subscribing
+    New,
+}
+
+/// An event ready to be appended, with its payload already serialized
+#[derive(Debug, Clone)]
+pub struct NewEvent {
+    pub aggregate_id: String,
+    pub aggregate_type: String,
+    pub event_type: String,
+    pub event_data: serde_json::Value,
+    pub header: EventHeader,
+}
+
+impl NewEvent {
+    /// Event with a fresh header, filed under the default aggregate type
+    pub fn new(aggregate_id: &str, event_type: &str, event_data: serde_json::Value) -> Self {
+        Self {
+            aggregate_id: aggregate_id.to_string(),
+            aggregate_type: DEFAULT_AGGREGATE_TYPE.to_string(),
+            event_type: event_type.to_string(),
+            event_data,
+            header: EventHeader::new(),
+        }
+    }
+    
+    /// Serialize a typed event
+    pub fn from_event<E: Event + Serialize>(aggregate_id: &str, event: &E, header: EventHeader) -> Result<Self> {
+        Ok(Self {
+            aggregate_id: aggregate_id.to_string(),
+            aggregate_type: event.aggregate_type().to_string(),
+            event_type: event.event_type().to_string(),
+            event_data: serde_json::to_value(event)?,
+            header,
+        })
+    }
+    
+    pub fn with_aggregate_type(mut self, aggregate_type: &str) -> Self {
+        self.aggregate_type = aggregate_type.to_string();
+        self
+    }
+    
+    pub fn with_header(mut self, header: EventHeader) -> Self {
+        self.header = header;
+        self
+    }
+}
+
+/// Event store trait for appending and retrieving events.
+///
+/// The trait is object safe, so stores can be chosen at runtime and shared as
+/// `Arc<dyn EventStore>`. Typed appends live in [`EventStoreExt`].
 #[async_trait]
 pub trait EventStore: Send + Sync {
-    /// Append an event to the store
-    async fn append_event<E: Event + Serialize + Send>(
+    /// Append a serialized event to the store.
+    ///
+    /// Aggregate IDs are unique across aggregate types: an event whose type
+    /// differs from the aggregate's existing events is rejected with
+    /// `AggregateTypeConflict`.
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata>;
+    
+    /// Append an event that already carries its CID, header and parent CID.
+    ///
+    /// Used to replay histories from another store; the CID is verified
+    /// against the event's canonical bytes and preserved as-is.
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata>;
+    
+    /// Lazily read an aggregate's events from a sequence onwards.
+    ///
+    /// Events are fetched as the stream is polled, so arbitrarily long
+    /// histories can be processed without buffering them.
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream>;
+    
+    /// Get events for an aggregate
+    async fn get_events(
         &self,
         aggregate_id: &str,
-        event: E,
-        parent_cid: Option<Cid>,
-    ) -> Result<EventMetadata>;
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>>;
     
-    /// Append an event with custom header
-    async fn append_event_with_header<E: Event + Serialize + Send>(
+    /// Get all events sharing a correlation ID, in store order
+    async fn get_events_by_correlation(
         &self,
-        aggregate_id: &str,
-        event: E,
-        header: EventHeader,
-        parent_cid: Option<Cid>,
-    ) -> Result<EventMetadata>;
+        correlation_id: &str,
+    ) -> Result<Vec<StoredEvent>>;
     
-    /// Get events for an aggregate
-    async fn get_events(
+    /// Get up to `limit` of an aggregate's events, newest first, starting at
+    /// `from_sequence` (inclusive) or at the head when `None`
+    async fn read_backward(
         &self,
         aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>>;
+    
+    /// Lazily read the events in a scope whose `timestamp` is at or after
+    /// `from` and before `to`, in store order.
+    ///
+    /// The bounds apply to [`StoredEvent::timestamp`], not to when a backend
+    /// stored the event, so imported and replayed events keep their place.
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream>;
+    
+    /// Get events of every aggregate of one type, in store order
+    async fn read_category(
+        &self,
+        aggregate_type: &str,
         from_sequence: u64,
         limit: usize,
     ) -> Result<Vec<StoredEvent>>;
     
-    /// Subscribe to events for an aggregate
+    /// Remove an aggregate's events stored before `before_sequence`,
+    /// returning how many were removed.
+    ///
+    /// This breaks the CID chain on its own; [`crate::lifecycle::truncate`]
+    /// writes a checkpoint first so the remaining events still verify.
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64>;
+    
+    /// List the aggregates in the store with their stream metadata, ordered
+    /// by type and then ID, one page at a time
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage>;
+    
+    /// Subscribe to the events in a scope, starting at the given position.
+    ///
+    /// Stored events are delivered first and the subscription then follows
+    /// new appends, with no gap or duplicate at the switch.
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>>;
+    
+    /// Subscribe to new events for an aggregate
     async fn subscribe_to_events(
         &self,
         aggregate_id: &str,
-    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>>;
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        self.subscribe_with(EventScope::Aggregate(aggregate_id.to_string()), SubscribeFrom::New).await
+    }
+    
+    /// Subscribe to new events of every aggregate of one type
+    async fn subscribe_category(
+        &self,
+        aggregate_type: &str,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        self.subscribe_with(EventScope::Category(aggregate_type.to_string()), SubscribeFrom::New).await
+    }
     
     /// Validate CID chain integrity
     async fn validate_cid_chain(
         &self,
         aggregate_id: &str,
-    ) -> Result<bool>;
+    ) -> Result<bool> {
+        let mut events = self.read_stream(aggregate_id, 0).await?;
+        let mut verifier = ChainVerifier::default();
+        
+        while let Some(event) = events.try_next().await? {
+            if !verifier.push(&event)? {
+                return Ok(false);
+            }
+        }
+        
+        Ok(verifier.finish())
+    }
+    
+    /// Resolve the payload of a stored event, fetching claim-checked data
+    async fn load_event_data(&self, event: &StoredEvent) -> Result<serde_json::Value> {
+        match &event.payload_cid {
+            Some(cid) => Err(EventStoreError::ContentStore(format!(
+                "Payload {} is held in a content store this backend cannot read",
+                cid
+            ))),
+            None => Ok(event.event_data.clone()),
+        }
+    }
 }
 
+/// Typed convenience methods available on every [`EventStore`]
+#[async_trait]
+pub trait EventStoreExt: EventStore {
+    /// Append an event to the store
+    async fn append_event<E: Event + Serialize + Send>(
+        &self,
+        aggregate_id: &str,
+        event: E,
+        parent_cid: Option<Cid>,
+    ) -> Result<EventMetadata> {
+        self.append_event_with_header(aggregate_id, event, EventHeader::new(), parent_cid).await
+    }
+    
+    /// Append an event with custom header
+    async fn append_event_with_header<E: Event + Serialize + Send>(
+        &self,
+        aggregate_id: &str,
+        event: E,
+        header: EventHeader,
+        parent_cid: Option<Cid>,
+    ) -> Result<EventMetadata> {
+        self.append(NewEvent::from_event(aggregate_id, &event, header)?, parent_cid).await
+    }
+}
+
+impl<S: EventStore + ?Sized> EventStoreExt for S {}
+
 /// JetStream-based event store implementation
 #[derive(Clone)]
 pub struct JetStreamEventStore {
     jetstream: jetstream::Context,
     stream_name: String,
-    ipfs_client: Option<Arc<IpfsClient>>,
-    subject_builder: SubjectBuilder,
+    namespace: SubjectNamespace,
+    content_store: Option<Arc<dyn ContentStore>>,
+    compression: CompressionPolicy,
+    claim_check: Option<ClaimCheck>,
+    cid_policy: CidPolicy,
+    append_guard: AppendGuard,
+    
+    /// Aggregate counts from the last listing, reused while the stream is
+    /// unchanged
+    aggregate_counts: Arc<std::sync::Mutex<Option<Arc<AggregateCounts>>>>,
+}
+
+/// Event count of every aggregate, optionally of one type, and the stream
+/// state they were read at
+#[derive(Debug)]
+struct AggregateCounts {
+    aggregate_type: Option<String>,
+    /// First and last sequence and message count of the stream
+    version: (u64, u64, u64),
+    counts: HashMap<AggregateKey, u64>,
+}
+
+/// How a publish is made conditional on the aggregate not having changed
+/// since its head was read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AppendGuard {
+    /// Expect the last sequence of the aggregate's subjects (server 2.11+)
+    Subject,
+    /// Expect the last sequence of the whole stream
+    Stream,
+}
+
+/// Condition a publish puts on the stream's state
+#[derive(Debug)]
+enum Expectation {
+    None,
+    LastSubjectSequence { subject: String, sequence: u64 },
+    LastSequence(u64),
+}
+
+/// The parts of an `INFO` reply that identify the server's API level
+#[derive(Deserialize)]
+struct AccountApi {
+    #[serde(default)]
+    api: ApiLevel,
+}
+
+#[derive(Deserialize, Default)]
+struct ApiLevel {
+    #[serde(default)]
+    level: u32,
+}
+
+/// Moves payloads above a size threshold into a content store
+#[derive(Clone)]
+struct ClaimCheck {
+    store: Arc<dyn ContentStore>,
+    threshold: usize,
+}
+
+/// `STREAM.INFO` request for the per-subject message counts of a stream
+#[derive(Serialize)]
+struct SubjectsRequest<'a> {
+    subjects_filter: &'a str,
+    offset: usize,
+}
+
+/// The parts of a `STREAM.INFO` reply that carry subject counts
+#[derive(Deserialize)]
+struct SubjectsInfo {
+    /// Number of matching subjects across all pages
+    #[serde(default)]
+    total: usize,
+    state: SubjectsState,
+}
+
+#[derive(Deserialize)]
+struct SubjectsState {
+    #[serde(default)]
+    subjects: HashMap<String, u64>,
 }
 
 impl JetStreamEventStore {
-    /// Create a new JetStream event store
+    /// Create a new JetStream event store with default stream settings
     pub async fn new(
         jetstream: jetstream::Context,
         stream_name: &str,
     ) -> Result<Self> {
-        // Create or update the stream configuration
-        let stream_config = stream::Config {
-            name: stream_name.to_string(),
-            subjects: vec![
-                format!("events.>"),  // All events
-            ],
-            retention: stream::RetentionPolicy::Limits,
-            storage: stream::StorageType::File,
-            max_age: std::time::Duration::from_days(365),
-            duplicate_window: std::time::Duration::from_secs(120),
-            ..Default::default()
-        };
-        
-        // Create or update stream
-        match jetstream.create_stream(stream_config.clone()).await {
-            Ok(_) => {},
-            Err(jetstream::Error::StreamNameExists) => {
-                jetstream.update_stream(stream_config).await?;
-            },
-            Err(e) => return Err(e.into()),
-        }
+        Self::with_config(jetstream, EventStoreConfig::new(stream_name)).await
+    }
+    
+    /// Create an event store, creating its stream or reconciling an
+    /// existing one with the given configuration
+    pub async fn with_config(
+        jetstream: jetstream::Context,
+        config: EventStoreConfig,
+    ) -> Result<Self> {
+        config::ensure_stream(&jetstream, &config).await?;
         
-        // Initialize subject builder for event routing
-        let subject_builder = SubjectBuilder::new("events");
+        // Servers before 2.11 report no API level and cannot expect the last
+        // sequence of a wildcard subject
+        let account: Response<AccountApi> = jetstream.request("INFO", b"").await?;
+        let append_guard = match account {
+            Response::Ok(account) if account.api.level >= 1 => AppendGuard::Subject,
+            _ => AppendGuard::Stream,
+        };
         
         Ok(Self {
             jetstream,
-            stream_name: stream_name.to_string(),
-            ipfs_client: None, // Can be added later for CID storage
-            subject_builder,
+            stream_name: config.stream_name().to_string(),
+            namespace: config.namespace().clone(),
+            content_store: None, // Can be added later for CID storage
+            compression: CompressionPolicy::disabled(),
+            claim_check: None,
+            cid_policy: CidPolicy::default(),
+            append_guard,
+            aggregate_counts: Arc::default(),
         })
     }
     
+    /// Persist the canonical bytes of every event in a content store
+    pub fn with_content_store(mut self, store: impl ContentStore + 'static) -> Self {
+        self.content_store = Some(Arc::new(store));
+        self
+    }
+    
     /// Enable IPFS for CID storage
-    pub fn with_ipfs(mut self, ipfs_client: IpfsClient) -> Self {
-        self.ipfs_client = Some(Arc::new(ipfs_client));
+    #[cfg(feature = "ipfs")]
+    pub fn with_ipfs(self, ipfs_client: ipfs_api::IpfsClient) -> Self {
+        self.with_content_store(crate::content_store::IpfsContentStore::new(ipfs_client))
+    }
+    
+    /// Compress large payloads before they are published
+    pub fn with_compression(mut self, policy: CompressionPolicy) -> Self {
+        self.compression = policy;
         self
     }
     
-    /// Generate subject for an event
-    fn event_subject(&self, aggregate_id: &str, event_type: &str) -> Subject {
-        self.subject_builder
-            .with_entity(aggregate_id)
-            .with_action(event_type)
-            .build()
-    }
-    
-    /// Store event data in IPFS and return CID
-    async fn store_in_ipfs(&self, data: &[u8]) -> Result<Cid> {
-        if let Some(ipfs) = &self.ipfs_client {
-            match ipfs.add(data).await {
-                Ok(res) => {
-                    let cid = Cid::try_from(res.hash.as_str())
-                        .map_err(|e| EventStoreError::Ipfs(e.to_string()))?;
-                    Ok(cid)
-                }
-                Err(e) => Err(EventStoreError::Ipfs(e.to_string()))
-            }
-        } else {
-            // Generate a deterministic CID without IPFS
-            let cid = self.generate_local_cid(data);
-            Ok(cid)
+    /// Store payloads of at least `threshold` bytes in a content store,
+    /// keeping only their CID in the event
+    pub fn with_claim_check(mut self, store: impl ContentStore + 'static, threshold: usize) -> Self {
+        self.claim_check = Some(ClaimCheck {
+            store: Arc::new(store),
+            threshold,
+        });
+        self
+    }
+    
+    /// Choose the hash function and codec for new CIDs.
+    ///
+    /// Existing events keep their CIDs; verification reads the multihash
+    /// code from each CID, so chains may mix policies.
+    pub fn with_cid_policy(mut self, policy: CidPolicy) -> Self {
+        self.cid_policy = policy;
+        self
+    }
+    
+    /// Subject filters matching every event of an aggregate, including
+    /// events published before subjects carried a category
+    fn aggregate_filters(&self, aggregate_id: &str) -> Result<Vec<String>> {
+        self.namespace.aggregate_filters(None, aggregate_id)
+    }
+    
+    /// Subject filter matching every event in the store
+    fn all_events_filter(&self) -> String {
+        self.namespace.all_events()
+    }
+    
+    /// Subject filters matching every event in a scope
+    fn scope_filters(&self, scope: &EventScope) -> Result<Vec<String>> {
+        match scope {
+            EventScope::All => Ok(vec![self.all_events_filter()]),
+            EventScope::Aggregate(aggregate_id) => self.aggregate_filters(aggregate_id),
+            EventScope::Category(aggregate_type) => self.namespace.category_filters(aggregate_type),
         }
     }
     
-    /// Generate a local CID for testing without IPFS
-    fn generate_local_cid(&self, data: &[u8]) -> Cid {
-        use sha2::{Sha256, Digest};
-        let hash = Sha256::digest(data);
+    /// Generate subject for an event
+    fn event_subject(&self, aggregate_type: &str, aggregate_id: &str, event_type: &str) -> Result<Subject> {
+        Ok(self.namespace
+            .category_builder(aggregate_type)?
+            .with_entity(&encode_token(aggregate_id)?)
+            .with_action(&encode_token(event_type)?)
+            .build())
+    }
+    
+    /// Compute the CID of event data and persist it in the content store, if any
+    async fn persist_content(&self, data: &[u8]) -> Result<Cid> {
+        let cid = self.generate_local_cid(data);
         
-        // Create a CID v1 with raw codec
-        cid::Cid::new_v1(0x55, multihash::Multihash::wrap(0x12, &hash).unwrap())
+        if let Some(store) = &self.content_store {
+            store.put(&cid, data).await?;
+        }
+        
+        Ok(cid)
     }
     
-    /// Get the latest event for CID chain validation
-    async fn get_latest_event(&self, aggregate_id: &str) -> Result<Option<StoredEvent>> {
-        let events = self.get_events(aggregate_id, 0, 1).await?;
-        Ok(events.into_iter().next())
+    /// Generate a deterministic local CID under the store's policy
+    fn generate_local_cid(&self, data: &[u8]) -> Cid {
+        self.cid_policy.cid(data)
     }
-}
-
-#[async_trait]
-impl EventStore for JetStreamEventStore {
-    async fn append_event<E: Event + Serialize + Send>(
+    
+    /// Move an oversized payload into the claim-check store.
+    ///
+    /// Returns the payload to embed in the event and the CID of the stored
+    /// blob, if the payload was claim-checked.
+    async fn claim_check_payload(
         &self,
-        aggregate_id: &str,
-        event: E,
-        parent_cid: Option<Cid>,
-    ) -> Result<EventMetadata> {
-        let header = EventHeader {
-            message_id: Uuid::new_v4().to_string(),
-            correlation_id: Uuid::new_v4().to_string(),
-            causation_id: None,
-            timestamp: chrono::Utc::now(),
+        event_data: serde_json::Value,
+    ) -> Result<(serde_json::Value, Option<String>)> {
+        let Some(claim_check) = &self.claim_check else {
+            return Ok((event_data, None));
         };
         
-        self.append_event_with_header(aggregate_id, event, header, parent_cid).await
+        let bytes = serde_json::to_vec(&event_data)?;
+        if bytes.len() < claim_check.threshold {
+            return Ok((event_data, None));
+        }
+        
+        let cid = self.generate_local_cid(&bytes);
+        claim_check.store.put(&cid, &bytes).await?;
+        
+        Ok((serde_json::Value::Null, Some(cid.to_string())))
     }
     
-    async fn append_event_with_header<E: Event + Serialize + Send>(
+    /// Read an aggregate's latest event, together with the expectation that
+    /// fails a publish if anything was appended to the aggregate since
+    async fn head(&self, aggregate_id: &str) -> Result<(Option<StoredEvent>, Expectation)> {
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
+        // Match every category, so an append under another type sees the
+        // aggregate and racing appends of two types conflict
+        let subject = self.namespace.aggregate_filter(aggregate_id)?;
+        let latest = self.read_backward_filter(&stream, &subject, None, 1).await?.pop();
+        
+        let expectation = match self.append_guard {
+            AppendGuard::Subject => Expectation::LastSubjectSequence {
+                sequence: latest.as_ref().map_or(0, |event| event.sequence),
+                subject,
+            },
+            // Read before the head, so any later append fails the publish
+            AppendGuard::Stream => Expectation::LastSequence(stream.cached_info().state.last_sequence),
+        };
+        
+        // Nothing is appended under the legacy layout any more, so it only
+        // supplies the head
+        let latest = match latest {
+            None => {
+                let legacy = self.namespace.legacy_aggregate_filter(aggregate_id)?;
+                self.read_backward_filter(&stream, &legacy, None, 1).await?.pop()
+            }
+            latest => latest,
+        };
+        
+        Ok((latest, expectation))
+    }
+    
+    /// Publish an event checked against the aggregate's head.
+    ///
+    /// A parent CID makes the publish conditional on the head not having
+    /// moved. A refused append is still sent, with an expectation no stream
+    /// can meet: the server checks `Nats-Msg-Id` first, so a retry of an
+    /// event it already stored gets the original receipt and anything else
+    /// is rejected without being written.
+    async fn publish_checked(
         &self,
-        aggregate_id: &str,
-        event: E,
-        header: EventHeader,
-        parent_cid: Option<Cid>,
+        event: &StoredEvent,
+        cid: Cid,
+        latest: Option<&StoredEvent>,
+        parent_cid: Option<&Cid>,
+        expectation: Expectation,
     ) -> Result<EventMetadata> {
-        // Validate parent CID if provided
-        if let Some(ref parent) = parent_cid {
-            let latest = self.get_latest_event(aggregate_id).await?;
-            if let Some(latest_event) = latest {
-                if let Some(latest_cid_str) = &latest_event.cid {
-                    let latest_cid = Cid::try_from(latest_cid_str.as_str())
-                        .map_err(|e| EventStoreError::InvalidCidChain(e.to_string()))?;
-                    
-                    if &latest_cid != parent {
-                        return Err(EventStoreError::InvalidCidChain(
-                            "Parent CID does not match latest event".to_string()
-                        ));
-                    }
-                }
-            }
+        // Past the server's duplicate window a retry is still known while it
+        // is the head
+        if let Some(latest) = latest.filter(|latest| latest.header.message_id == event.header.message_id) {
+            return duplicate_receipt(latest);
         }
         
-        // Create stored event
-        let event_data = serde_json::to_value(&event)?;
-        let mut stored_event = StoredEvent {
-            sequence: 0, // Will be set by JetStream
-            aggregate_id: aggregate_id.to_string(),
-            event_type: event.event_type().to_string(),
-            event_data,
-            header: header.clone(),
-            cid: None,
-            parent_cid: parent_cid.map(|c| c.to_string()),
-            timestamp: chrono::Utc::now(),
-        };
-        
-        // Serialize for storage
-        let event_bytes = serde_json::to_vec(&stored_event)?;
+        let refusal = check_append(latest, event, parent_cid).err();
         
-        // Generate CID
-        let cid = self.store_in_ipfs(&event_bytes).await?;
-        stored_event.cid = Some(cid.to_string());
+        let expectation = match (&refusal, parent_cid) {
+            (Some(_), _) => Expectation::LastSequence(u64::MAX),
+            (None, Some(_)) => expectation,
+            (None, None) => Expectation::None,
+        };
         
-        // Re-serialize with CID
-        let final_bytes = serde_json::to_vec(&stored_event)?;
+        match (self.publish(event, cid, expectation).await, refusal) {
+            (Err(EventStoreError::ConcurrentModification), Some(refusal)) => Err(refusal),
+            (result, _) => result,
+        }
+    }
+    
+    /// Publish a fully built event, CID included, to JetStream
+    async fn publish(&self, stored_event: &StoredEvent, cid: Cid, expectation: Expectation) -> Result<EventMetadata> {
+        // Compress if the policy asks for it
+        let final_bytes = serde_json::to_vec(stored_event)?;
+        let (codec, payload) = self.compression.compress(&stored_event.event_type, final_bytes)?;
         
         // Create NATS headers with message identity
+        let header = &stored_event.header;
         let mut headers = async_nats::HeaderMap::new();
         headers.insert("X-Message-ID", header.message_id.as_str());
+        // JetStream drops repeats of this ID within the stream's duplicate window
+        headers.insert("Nats-Msg-Id", header.message_id.as_str());
         headers.insert("X-Correlation-ID", header.correlation_id.as_str());
         if let Some(ref causation) = header.causation_id {
             headers.insert("X-Causation-ID", causation.as_str());
         }
         headers.insert("X-CID", cid.to_string().as_str());
-        if let Some(ref parent) = parent_cid {
-            headers.insert("X-Parent-CID", parent.to_string().as_str());
+        if let Some(ref parent) = stored_event.parent_cid {
+            headers.insert("X-Parent-CID", parent.as_str());
+        }
+        if let Some(ref payload) = stored_event.payload_cid {
+            headers.insert("X-Payload-CID", payload.as_str());
+        }
+        if let Some(codec) = codec {
+            headers.insert(CONTENT_ENCODING_HEADER, codec.as_str());
+        }
+        match expectation {
+            Expectation::None => {}
+            Expectation::LastSubjectSequence { subject, sequence } => {
+                headers.insert("Nats-Expected-Last-Subject-Sequence", sequence.to_string().as_str());
+                headers.insert("Nats-Expected-Last-Subject-Sequence-Subject", subject.as_str());
+            }
+            Expectation::LastSequence(sequence) => {
+                headers.insert("Nats-Expected-Last-Sequence", sequence.to_string().as_str());
+            }
         }
         
         // Publish to JetStream
-        let subject = self.event_subject(aggregate_id, event.event_type());
-        let ack = self.jetstream
-            .publish_with_headers(subject.to_string(), headers, final_bytes.into())
+        let subject = self.event_subject(
+            stored_event.category(),
+            &stored_event.aggregate_id,
+            &stored_event.event_type,
+        )?;
+        let ack = match self.jetstream
+            .publish_with_headers(subject.to_string(), headers, payload.into())
             .await?
-            .await?;
+            .await
+        {
+            Ok(ack) => ack,
+            Err(e) if e.kind() == PublishErrorKind::WrongLastSequence => {
+                return Err(EventStoreError::ConcurrentModification);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        
+        if ack.duplicate {
+            // Report the event that was stored the first time, not this attempt
+            let stream = self.jetstream.get_stream(&self.stream_name).await?;
+            let original = stream
+                .direct_get_next_for_subject(self.all_events_filter(), Some(ack.sequence))
+                .await?;
+            return duplicate_receipt(&self.decode_direct(&original)?);
+        }
         
         Ok(EventMetadata {
             sequence: ack.sequence,
             cid: Some(cid),
             timestamp: stored_event.timestamp,
+            duplicate: false,
         })
     }
     
-    async fn get_events(
+    /// Lazily read every event matching subject filters from a sequence, in
+    /// store order.
+    ///
+    /// Uses an ordered ephemeral consumer, which the server removes once the
+    /// returned stream is dropped. The stream ends at the last event that
+    /// existed when it was opened.
+    async fn stream_filter(&self, filters: Vec<String>, from_sequence: u64) -> Result<ReadStream> {
+        let deliver_policy = consumer::DeliverPolicy::ByStartSequence {
+            start_sequence: from_sequence.max(1),
+        };
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
+        let mut consumer = stream.create_consumer(ordered_config(filters, deliver_policy)).await?;
+        
+        let pending = consumer.info().await?.num_pending;
+        let messages = consumer.messages().await?;
+        let namespace = self.namespace.clone();
+        
+        let events = futures::stream::try_unfold((messages, pending == 0), move |(mut messages, done)| {
+            let namespace = namespace.clone();
+            async move {
+                if done {
+                    return Ok(None);
+                }
+                let Some(msg) = messages.try_next().await? else {
+                    return Ok(None);
+                };
+                
+                namespace.ensure_contains(&msg.subject)?;
+                let info = msg.info()?;
+                let mut event = decode_message(&msg)?;
+                event.sequence = info.stream_sequence;
+                
+                Ok(Some((event, (messages, info.pending == 0))))
+            }
+        });
+        
+        Ok(Box::pin(events))
+    }
+    
+    /// Subscribe to events matching subject filters.
+    ///
+    /// A single ordered consumer delivers the stored events and then follows
+    /// the stream, so history and live events join without a gap or duplicate.
+    /// The consumer is ephemeral and removed by the server once the
+    /// subscription is dropped.
+    async fn subscribe_filter(
         &self,
-        aggregate_id: &str,
-        from_sequence: u64,
-        limit: usize,
-    ) -> Result<Vec<StoredEvent>> {
-        // Create consumer for reading events
-        let consumer_config = consumer::pull::Config {
-            filter_subject: format!("events.{}.>", aggregate_id),
-            deliver_policy: consumer::DeliverPolicy::ByStartSequence {
-                start_sequence: from_sequence.max(1),
-            },
-            ..Default::default()
+        filters: Vec<String>,
+        deliver_policy: consumer::DeliverPolicy,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
+        let consumer = stream.create_consumer(ordered_config(filters, deliver_policy)).await?;
+        
+        let messages = consumer
+            .messages()
+            .await?
+            .map_err(|e| -> async_nats::Error { Box::new(e) });
+        
+        // Create a stream that converts messages to StoredEvents
+        let event_stream = SubscriptionStream {
+            messages: Box::pin(messages),
+            namespace: self.namespace.clone(),
         };
         
-        let consumer = self.jetstream
-            .create_consumer(&self.stream_name, consumer_config)
-            .await?;
+        Ok(Box::new(event_stream))
+    }
+    
+    /// Newest events matching any of the filters, newest first
+    async fn read_backward_filters(
+        &self,
+        filters: Vec<String>,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
         
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
         let mut events = Vec::new();
-        let mut messages = consumer.messages().await?;
-        
-        for _ in 0..limit {
-            match messages.try_next().await {
-                Ok(Some(msg)) => {
-                    let mut event: StoredEvent = serde_json::from_slice(&msg.payload)?;
-                    event.sequence = msg.info().stream_sequence;
-                    events.push(event);
-                    msg.ack().await?;
+        for filter in &filters {
+            events.extend(self.read_backward_filter(&stream, filter, from_sequence, limit).await?);
+        }
+        
+        // The newest `limit` of the union are among each filter's newest `limit`
+        events.sort_by_key(|e| std::cmp::Reverse(e.sequence));
+        events.truncate(limit);
+        Ok(events)
+    }
+    
+    /// Newest events matching one filter, newest first
+    async fn read_backward_filter(
+        &self,
+        stream: &jetstream::stream::Stream,
+        filter: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        // Start from the given sequence, or below the filter's newest event,
+        // which is the first result
+        let mut events = Vec::with_capacity(limit);
+        let mut upper = match from_sequence {
+            Some(sequence) => sequence,
+            None => {
+                let head = match stream.direct_get_last_for_subject(filter).await {
+                    Ok(msg) => self.decode_direct(&msg)?,
+                    Err(e) if e.kind() == DirectGetErrorKind::NotFound => return Ok(Vec::new()),
+                    Err(e) => return Err(EventStoreError::Nats(e.into())),
+                };
+                let upper = head.sequence - 1;
+                events.push(head);
+                upper
+            }
+        };
+        
+        // There is no "previous message for subject" lookup, so scan windows
+        // below the upper bound forwards, doubling the window each time
+        let mut window = (limit as u64).max(16) * 4;
+        while events.len() < limit && upper > 0 {
+            let lower = upper.saturating_sub(window - 1).max(1);
+            
+            let mut batch = Vec::new();
+            let mut next = lower;
+            loop {
+                let msg = match stream.direct_get_next_for_subject(filter, Some(next)).await {
+                    Ok(msg) => msg,
+                    Err(e) if e.kind() == DirectGetErrorKind::NotFound => break,
+                    Err(e) => return Err(EventStoreError::Nats(e.into())),
+                };
+                let event = self.decode_direct(&msg)?;
+                if event.sequence > upper {
+                    break;
                 }
-                Ok(None) => break,
-                Err(_) => break,
+                next = event.sequence + 1;
+                batch.push(event);
             }
+            
+            let remaining = limit - events.len();
+            events.extend(batch.into_iter().rev().take(remaining));
+            upper = lower - 1;
+            window = window.saturating_mul(2);
         }
         
         Ok(events)
     }
     
-    async fn subscribe_to_events(
+    /// First and last events matching any of the filters
+    async fn first_and_last(
         &self,
-        aggregate_id: &str,
-    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
-        // Create push consumer for real-time subscription
-        let consumer_config = consumer::push::Config {
-            durable_name: Some(format!("sub-{}", Uuid::new_v4())),
-            filter_subject: format!("events.{}.>", aggregate_id),
-            deliver_policy: consumer::DeliverPolicy::New,
-            ..Default::default()
+        stream: &jetstream::stream::Stream,
+        filters: &[String],
+    ) -> Result<Option<(StoredEvent, StoredEvent)>> {
+        let mut bounds: Option<(StoredEvent, StoredEvent)> = None;
+        for filter in filters {
+            let first = match stream.direct_get_next_for_subject(filter, None).await {
+                Ok(msg) => self.decode_direct(&msg)?,
+                Err(e) if e.kind() == DirectGetErrorKind::NotFound => continue,
+                Err(e) => return Err(EventStoreError::Nats(e.into())),
+            };
+            let last = stream.direct_get_last_for_subject(filter).await?;
+            let last = self.decode_direct(&last)?;
+            
+            bounds = Some(match bounds {
+                Some((a, b)) => (
+                    if a.sequence <= first.sequence { a } else { first },
+                    if b.sequence >= last.sequence { b } else { last },
+                ),
+                None => (first, last),
+            });
+        }
+        
+        Ok(bounds)
+    }
+    
+    /// Decode an event returned by a direct get, which carries its stream
+    /// sequence and original subject in headers
+    fn decode_direct(&self, msg: &async_nats::Message) -> Result<StoredEvent> {
+        let header = |name: &str| {
+            msg.headers
+                .as_ref()
+                .and_then(|headers| headers.get(name))
+                .map(|value| value.as_str().to_string())
         };
         
-        let consumer = self.jetstream
-            .create_consumer(&self.stream_name, consumer_config)
-            .await?;
+        let subject = header("Nats-Subject").unwrap_or_else(|| msg.subject.to_string());
+        self.namespace.ensure_contains(&subject)?;
         
-        let messages = consumer.messages().await?;
+        let mut event = decode_message(msg)?;
+        event.sequence = header("Nats-Sequence")
+            .and_then(|sequence| sequence.parse().ok())
+            .ok_or_else(|| EventStoreError::EventNotFound("Direct get reply without a sequence".to_string()))?;
         
-        // Create a stream that converts messages to StoredEvents
-        let event_stream = EventStream {
-            messages: Box::pin(messages),
+        Ok(event)
+    }
+    
+    /// Event count of every aggregate, optionally of one type.
+    ///
+    /// The counts come from the subject state of the whole stream, so they are
+    /// kept while the stream is unchanged and later pages of a listing reuse
+    /// them.
+    async fn aggregate_counts(
+        &self,
+        stream: &jetstream::stream::Stream,
+        aggregate_type: Option<&str>,
+    ) -> Result<Arc<AggregateCounts>> {
+        let state = &stream.cached_info().state;
+        let version = (state.first_sequence, state.last_sequence, state.messages);
+        let cached = self.aggregate_counts.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        if let Some(cached) = cached.filter(|c| c.version == version && c.aggregate_type.as_deref() == aggregate_type) {
+            return Ok(cached);
+        }
+        
+        let filters = match aggregate_type {
+            Some(aggregate_type) => self.namespace.category_filters(aggregate_type)?,
+            None => vec![self.all_events_filter()],
         };
         
-        Ok(Box::new(event_stream))
+        // The stream keeps a message count per subject, and each subject is
+        // one event type of one aggregate
+        let mut counts: HashMap<AggregateKey, u64> = HashMap::new();
+        for filter in &filters {
+            for (subject, count) in self.subject_counts(filter).await? {
+                if let Some((aggregate_type, aggregate_id, _)) = self.namespace.parse_event_subject(&subject)? {
+                    *counts.entry(AggregateKey::new(&aggregate_type, &aggregate_id)).or_default() += count;
+                }
+            }
+        }
+        
+        let counts = Arc::new(AggregateCounts {
+            aggregate_type: aggregate_type.map(str::to_string),
+            version,
+            counts,
+        });
+        *self.aggregate_counts.lock().unwrap_or_else(|e| e.into_inner()) = Some(counts.clone());
+        Ok(counts)
     }
     
-    async fn validate_cid_chain(
+    /// Message count of every stream subject matching a filter.
+    ///
+    /// The client's stream info does not expose per-subject state, so this
+    /// sends the `STREAM.INFO` request itself, one page at a time.
+    async fn subject_counts(&self, filter: &str) -> Result<HashMap<String, u64>> {
+        let mut counts = HashMap::new();
+        loop {
+            let request = SubjectsRequest {
+                subjects_filter: filter,
+                offset: counts.len(),
+            };
+            let response: Response<SubjectsInfo> = self
+                .jetstream
+                .request(format!("STREAM.INFO.{}", self.stream_name), &request)
+                .await?;
+            let info = match response {
+                Response::Ok(info) => info,
+                Response::Err { error } => return Err(error.into()),
+            };
+            
+            let page = info.state.subjects.len();
+            counts.extend(info.state.subjects);
+            if page == 0 || counts.len() >= info.total {
+                return Ok(counts);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventStore for JetStreamEventStore {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        let (latest, expectation) = self.head(&event.aggregate_id).await?;
+        
+        // Create stored event
+        let (event_data, payload_cid) = self
+            .claim_check_payload(event.event_data)
+            .await?;
+        let mut stored_event = StoredEvent {
+            sequence: 0, // Will be set by JetStream
+            aggregate_id: event.aggregate_id,
+            aggregate_type: Some(event.aggregate_type),
+            event_type: event.event_type,
+            event_data,
+            payload_cid,
+            header: event.header,
+            cid: None,
+            parent_cid: parent_cid.as_ref().map(|c| c.to_string()),
+            timestamp: chrono::Utc::now(),
+        };
+        
+        // Serialize for storage
+        let event_bytes = stored_event.canonical_bytes()?;
+        
+        // Generate CID over the uncompressed canonical form
+        let cid = self.persist_content(&event_bytes).await?;
+        stored_event.cid = Some(cid.to_string());
+        
+        self.publish_checked(&stored_event, cid, latest.as_ref(), parent_cid.as_ref(), expectation).await
+    }
+    
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        let cid = event.verified_cid()?;
+        let parent_cid = event.parent_cid_value()?;
+        
+        let (latest, expectation) = self.head(&event.aggregate_id).await?;
+        self.persist_content(&event.canonical_bytes()?).await?;
+        
+        self.publish_checked(&event, cid, latest.as_ref(), parent_cid.as_ref(), expectation).await
+    }
+    
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        self.stream_filter(self.aggregate_filters(aggregate_id)?, from_sequence).await
+    }
+    
+    async fn get_events(
         &self,
         aggregate_id: &str,
-    ) -> Result<bool> {
-        let events = self.get_events(aggregate_id, 0, 1000).await?;
-        
-        if events.is_empty() {
-            return Ok(true);
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.read_stream(aggregate_id, from_sequence)
+            .await?
+            .take(limit)
+            .try_collect()
+            .await
+    }
+    
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.read_backward_filters(self.aggregate_filters(aggregate_id)?, from_sequence, limit).await
+    }
+    
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        // Event timestamps are set by the writer and need not follow store
+        // order, so the whole scope is read rather than a start-time window
+        let filters = self.scope_filters(&scope)?;
+        let events = self
+            .stream_filter(filters, 0)
+            .await?
+            .try_filter(move |event| future::ready(event.timestamp >= from && event.timestamp < to));
+        Ok(Box::pin(events))
+    }
+    
+    async fn read_category(
+        &self,
+        aggregate_type: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.stream_filter(self.namespace.category_filters(aggregate_type)?, from_sequence)
+            .await?
+            .take(limit)
+            .try_collect()
+            .await
+    }
+    
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        // A purge without an upper sequence would remove everything
+        if before_sequence <= 1 {
+            return Ok(0);
         }
         
-        // First event should have no parent
-        if events[0].parent_cid.is_some() {
-            return Ok(false);
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
+        let mut purged = 0;
+        for filter in self.aggregate_filters(aggregate_id)? {
+            let response = stream
+                .purge()
+                .filter(filter)
+                .sequence(before_sequence)
+                .await
+                .map_err(|e| EventStoreError::Nats(e.into()))?;
+            purged += response.purged;
         }
         
-        // Validate chain
-        for i in 1..events.len() {
-            let expected_parent = &events[i - 1].cid;
-            let actual_parent = &events[i].parent_cid;
+        Ok(purged)
+    }
+    
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        let stream = self.jetstream.get_stream(&self.stream_name).await?;
+        let counts = self.aggregate_counts(&stream, query.aggregate_type.as_deref()).await?;
+        let counts = &counts.counts;
+        
+        let (keys, next) = query.page(counts.keys().cloned().collect())?;
+        
+        let mut aggregates = Vec::with_capacity(keys.len());
+        for key in keys {
+            let filters = self.namespace.aggregate_filters(Some(&key.aggregate_type), &key.aggregate_id)?;
+            let Some((first, last)) = self.first_and_last(&stream, &filters).await? else {
+                continue;
+            };
             
-            if expected_parent != actual_parent {
-                return Ok(false);
+            aggregates.push(AggregateInfo {
+                event_count: counts[&key],
+                aggregate_id: key.aggregate_id,
+                aggregate_type: key.aggregate_type,
+                first_sequence: first.sequence,
+                last_sequence: last.sequence,
+                head_cid: last.cid,
+                created_at: first.timestamp,
+                updated_at: last.timestamp,
+            });
+        }
+        
+        Ok(AggregatePage { aggregates, next })
+    }
+    
+    async fn get_events_by_correlation(
+        &self,
+        correlation_id: &str,
+    ) -> Result<Vec<StoredEvent>> {
+        // Scan the whole stream; correlation is carried in the event header
+        self.stream_filter(vec![self.all_events_filter()], 0)
+            .await?
+            .try_filter(|event| future::ready(event.header.correlation_id == correlation_id))
+            .try_collect()
+            .await
+    }
+    
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        let deliver_policy = match from {
+            SubscribeFrom::Beginning => consumer::DeliverPolicy::All,
+            SubscribeFrom::Sequence(sequence) => consumer::DeliverPolicy::ByStartSequence {
+                start_sequence: sequence.max(1),
+            },
+            SubscribeFrom::Timestamp(timestamp) => consumer::DeliverPolicy::ByStartTime {
+                start_time: to_offset_date_time(timestamp)?,
+            },
+            SubscribeFrom::Last => consumer::DeliverPolicy::Last,
+            SubscribeFrom::New => consumer::DeliverPolicy::New,
+        };
+        
+        self.subscribe_filter(self.scope_filters(&scope)?, deliver_policy).await
+    }
+    
+    async fn load_event_data(&self, event: &StoredEvent) -> Result<serde_json::Value> {
+        let Some(payload_cid) = &event.payload_cid else {
+            return Ok(event.event_data.clone());
+        };
+        
+        let claim_check = self.claim_check.as_ref().ok_or_else(|| {
+            EventStoreError::ContentStore(format!(
+                "No claim-check store configured to resolve payload {}",
+                payload_cid
+            ))
+        })?;
+        
+        let cid = Cid::try_from(payload_cid.as_str())
+            .map_err(|e| EventStoreError::ContentStore(e.to_string()))?;
+        
+        // The content store verifies the blob against its CID
+        let bytes = claim_check.store.get(&cid).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+/// Incrementally checks that events, in order, form an unbroken CID chain
+/// and that every CID matches its event
+#[derive(Debug, Default)]
+pub(crate) struct ChainVerifier {
+    previous_cid: Option<Option<String>>,
+    
+    /// Parent of a truncated stream's first event, until a checkpoint
+    /// vouches for it
+    unresolved_prefix: Option<String>,
+}
+
+impl ChainVerifier {
+    /// Check the next event; `Ok(false)` means the chain is broken
+    pub(crate) fn push(&mut self, event: &StoredEvent) -> Result<bool> {
+        // The first event has no parent, unless the stream was truncated;
+        // every later one links to its predecessor
+        match self.previous_cid.take() {
+            Some(expected_parent) if event.parent_cid != expected_parent => return Ok(false),
+            Some(_) => {}
+            None => self.unresolved_prefix = event.parent_cid.clone(),
+        }
+        
+        if let Some(checkpoint) = Checkpoint::from_event(event) {
+            if self.unresolved_prefix.as_ref() == Some(&checkpoint.prefix_head_cid) {
+                self.unresolved_prefix = None;
             }
         }
         
+        // Every CID must match its event; each CID names its own hash function
+        match event.verified_cid() {
+            Ok(_) => {}
+            Err(EventStoreError::InvalidCidChain(_)) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        
+        self.previous_cid = Some(event.cid.clone());
         Ok(true)
     }
+    
+    /// Whether the events pushed so far form a complete chain
+    pub(crate) fn finish(&self) -> bool {
+        self.unresolved_prefix.is_none()
+    }
+}
+
+/// Reject appending `event` after `latest` when the aggregate is closed or
+/// has another type, or when `parent_cid` is given and is not the latest
+/// event's CID
+pub(crate) fn check_append(latest: Option<&StoredEvent>, event: &StoredEvent, parent_cid: Option<&Cid>) -> Result<()> {
+    ensure_open(latest, &event.event_type)?;
+    ensure_same_type(latest, event)?;
+    
+    let Some(parent) = parent_cid else {
+        return Ok(());
+    };
+    
+    if let Some(latest_cid) = latest.and_then(|e| e.cid.as_deref()) {
+        if latest_cid != parent.to_string() {
+            return Err(EventStoreError::InvalidCidChain(
+                "Parent CID does not match latest event".to_string(),
+            ));
+        }
+    }
+    
+    Ok(())
+}
+
+/// Reject an event whose aggregate ID is already taken by an aggregate of
+/// another type.
+///
+/// Aggregate IDs are unique across types, so reads, purges and chain checks
+/// by ID never mix two categories.
+pub(crate) fn ensure_same_type(latest: Option<&StoredEvent>, event: &StoredEvent) -> Result<()> {
+    match latest {
+        Some(latest) if latest.category() != event.category() => Err(EventStoreError::AggregateTypeConflict(format!(
+            "Aggregate {} has type {}, not {}",
+            event.aggregate_id,
+            latest.category(),
+            event.category()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Receipt for an append of an event that is already stored
+fn duplicate_receipt(event: &StoredEvent) -> Result<EventMetadata> {
+    Ok(EventMetadata {
+        sequence: event.sequence,
+        cid: event.cid.as_deref().map(Cid::try_from).transpose()
+            .map_err(|e| EventStoreError::InvalidCidChain(e.to_string()))?,
+        timestamp: event.timestamp,
+        duplicate: true,
+    })
+}
+
+/// Convert a timestamp to the representation the NATS client uses
+fn to_offset_date_time(timestamp: chrono::DateTime<chrono::Utc>) -> Result<time::OffsetDateTime> {
+    let nanos = timestamp
+        .timestamp_nanos_opt()
+        .ok_or_else(|| EventStoreError::InvalidConfig(format!("Timestamp out of range: {}", timestamp)))?;
+    time::OffsetDateTime::from_unix_timestamp_nanos(nanos as i128)
+        .map_err(|e| EventStoreError::InvalidConfig(e.to_string()))
+}
+
+/// Decode a stored event from a JetStream message, undoing payload compression
+fn decode_message(msg: &async_nats::Message) -> Result<StoredEvent> {
+    let encoding = msg
+        .headers
+        .as_ref()
+        .and_then(|headers| headers.get(CONTENT_ENCODING_HEADER))
+        .map(|value| value.as_str());
+    let bytes = compression::decompress(encoding, &msg.payload)?;
+    Ok(serde_json::from_slice(&bytes)?)
 }
 
 // Stream implementation for event subscriptions
-use futures::stream::{Stream, StreamExt};
+use futures::future;
+use futures::stream::{Stream, StreamExt, TryStreamExt};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
-struct EventStream {
-    messages: Pin<Box<dyn Stream<Item = std::result::Result<async_nats::Message, async_nats::Error>> + Send>>,
+/// Ordered consumer over one or several subject filters
+fn ordered_config(mut filters: Vec<String>, deliver_policy: consumer::DeliverPolicy) -> consumer::pull::OrderedConfig {
+    let mut config = consumer::pull::OrderedConfig {
+        deliver_policy,
+        ..Default::default()
+    };
+    if filters.len() == 1 {
+        config.filter_subject = filters.remove(0);
+    } else {
+        config.filter_subjects = filters;
+    }
+    config
+}
+
+struct SubscriptionStream {
+    messages: Pin<Box<dyn Stream<Item = std::result::Result<jetstream::Message, async_nats::Error>> + Send>>,
+    namespace: SubjectNamespace,
 }
 
-impl Stream for EventStream {
+impl Stream for SubscriptionStream {
     type Item = StoredEvent;
     
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match self.messages.poll_next_unpin(cx) {
-            Poll::Ready(Some(Ok(msg))) => {
-                match serde_json::from_slice::<StoredEvent>(&msg.payload) {
-                    Ok(mut event) => {
-                        if let Some(info) = msg.info() {
-                            event.sequence = info.stream_sequence;
+        loop {
+            return match self.messages.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(msg))) => {
+                    // Never hand out events from another tenant's namespace
+                    if !self.namespace.contains(&msg.subject) {
+                        continue;
+                    }
+                    
+                    match decode_message(&msg) {
+                        Ok(mut event) => {
+                            if let Ok(info) = msg.info() {
+                                event.sequence = info.stream_sequence;
+                            }
+                            Poll::Ready(Some(event))
                         }
-                        Poll::Ready(Some(event))
+                        Err(_) => Poll::Ready(None),
                     }
-                    Err(_) => Poll::Ready(None),
                 }
-            }
-            Poll::Ready(Some(Err(_))) => Poll::Ready(None),
-            Poll::Ready(None) => Poll::Ready(None),
-            Poll::Pending => Poll::Pending,
+                Poll::Ready(Some(Err(_))) => Poll::Ready(None),
+                Poll::Ready(None) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            };
         }
     }
 }
\ No newline at end of file
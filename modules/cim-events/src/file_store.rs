@@ -0,0 +1,946 @@
+//! Native event store persisted in append-only segment files.
+//!
+//! For embedded and edge deployments without a NATS server. Records are
+//! appended to numbered segment files, each framed with its length and a
+//! checksum. An index of every live event, by sequence and by aggregate, is
+//! kept in memory and rebuilt from the segments when the store is opened.
+//! Recovery cuts off a record torn by a crash at the end of the last segment
+//! and checks the CIDs of that segment's events. Purges are records of their
+//! own; a segment file is deleted once it and every older segment hold no
+//! live events.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use cid::Cid;
+use futures::channel::mpsc;
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::RwLock;
+
+use crate::catalog::{AggregateInfo, AggregateKey, AggregatePage, AggregateQuery};
+use crate::cid_policy::CidPolicy;
+use crate::event_store::{
+    check_append, EventMetadata, EventScope, EventStore, EventStoreError, NewEvent, ReadStream, Result,
+    StoredEvent, SubscribeFrom,
+};
+
+/// Size at which a new segment is started by default
+pub const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+
+const SEGMENT_EXTENSION: &str = "seg";
+
+/// Length (u32) and checksum (u64) preceding every record
+const FRAME_HEADER_LEN: usize = 12;
+
+/// When appended records are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Sync every append before acknowledging it
+    Always,
+
+    /// Sync after every n appends; a crash may lose up to n - 1 acknowledged
+    /// events
+    Every(u32),
+
+    /// Leave flushing to the operating system
+    Never,
+}
+
+/// One framed entry of a segment
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "record", rename_all = "snake_case")]
+enum Record {
+    Event(Box<StoredEvent>),
+    Purge {
+        aggregate_id: String,
+        before_sequence: u64,
+
+        /// Highest sequence assigned when the purge was written, so purging
+        /// the newest events never lets their sequences be reused
+        last_sequence: u64,
+    },
+}
+
+/// Where a live event is stored
+#[derive(Debug, Clone)]
+struct IndexEntry {
+    /// First sequence of the segment, which names it
+    segment: u64,
+
+    /// Offset and length of the record's payload
+    offset: u64,
+    len: u32,
+
+    aggregate_id: String,
+    category: String,
+    message_id: String,
+    timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+impl IndexEntry {
+    fn matches(&self, scope: &EventScope) -> bool {
+        match scope {
+            EventScope::All => true,
+            EventScope::Aggregate(aggregate_id) => &self.aggregate_id == aggregate_id,
+            EventScope::Category(aggregate_type) => &self.category == aggregate_type,
+        }
+    }
+}
+
+/// Where an event's record was stored when it was looked up, so it can be
+/// read without holding the lock
+struct Location {
+    sequence: u64,
+    path: PathBuf,
+    offset: u64,
+    len: u32,
+}
+
+struct Segment {
+    path: PathBuf,
+    len: u64,
+
+    /// Events in the segment that were not purged
+    live: usize,
+}
+
+struct Subscriber {
+    scope: EventScope,
+    sender: mpsc::UnboundedSender<StoredEvent>,
+}
+
+struct LogState {
+    segments: BTreeMap<u64, Segment>,
+
+    /// Open for appending to the newest segment
+    writer: File,
+    unsynced: u32,
+
+    index: BTreeMap<u64, IndexEntry>,
+    aggregates: HashMap<String, Vec<u64>>,
+    seen: HashMap<String, EventMetadata>,
+    last_sequence: u64,
+    subscribers: Vec<Subscriber>,
+
+    /// Set when a failed write could not be cut off the active segment, or
+    /// a written record could not be synced; later writes are refused rather
+    /// than appended after a record in an unknown state
+    failed: Option<String>,
+}
+
+impl LogState {
+    fn active_segment(&self) -> u64 {
+        *self.segments.keys().next_back().expect("the log always has an active segment")
+    }
+
+    fn insert(&mut self, event: &StoredEvent, segment: u64, offset: u64, len: u32) {
+        self.index.insert(
+            event.sequence,
+            IndexEntry {
+                segment,
+                offset,
+                len,
+                aggregate_id: event.aggregate_id.clone(),
+                category: event.category().to_string(),
+                message_id: event.header.message_id.clone(),
+                timestamp: event.timestamp,
+            },
+        );
+        self.aggregates.entry(event.aggregate_id.clone()).or_default().push(event.sequence);
+        self.seen.insert(
+            event.header.message_id.clone(),
+            EventMetadata {
+                sequence: event.sequence,
+                cid: event.cid.as_deref().and_then(|cid| Cid::try_from(cid).ok()),
+                timestamp: event.timestamp,
+                duplicate: true,
+            },
+        );
+        self.last_sequence = self.last_sequence.max(event.sequence);
+        if let Some(segment) = self.segments.get_mut(&segment) {
+            segment.live += 1;
+        }
+    }
+
+    /// Drop an aggregate's events before a sequence from the index, and
+    /// forget their message IDs
+    fn remove_prefix(&mut self, aggregate_id: &str, before_sequence: u64) -> u64 {
+        let Some(sequences) = self.aggregates.get_mut(aggregate_id) else {
+            return 0;
+        };
+
+        let end = sequences.partition_point(|sequence| *sequence < before_sequence);
+        let removed: Vec<u64> = sequences.drain(..end).collect();
+        if sequences.is_empty() {
+            self.aggregates.remove(aggregate_id);
+        }
+
+        for sequence in &removed {
+            if let Some(entry) = self.index.remove(sequence) {
+                self.seen.remove(&entry.message_id);
+                if let Some(segment) = self.segments.get_mut(&entry.segment) {
+                    segment.live -= 1;
+                }
+            }
+        }
+
+        removed.len() as u64
+    }
+
+    /// Sequences of an aggregate's live events, oldest first
+    fn sequences_of(&self, aggregate_id: &str) -> &[u64] {
+        self.aggregates.get(aggregate_id).map_or(&[], Vec::as_slice)
+    }
+
+    /// Live events matching a predicate, from a sequence on
+    fn select(&self, from_sequence: u64, matches: impl Fn(&IndexEntry) -> bool) -> Vec<u64> {
+        self.index
+            .range(from_sequence..)
+            .filter(|(_, entry)| matches(entry))
+            .map(|(sequence, _)| *sequence)
+            .collect()
+    }
+
+    /// Where the records of events are stored, skipping any no longer indexed
+    fn locate(&self, sequences: &[u64]) -> Vec<Location> {
+        sequences
+            .iter()
+            .filter_map(|sequence| {
+                let entry = self.index.get(sequence)?;
+                Some(Location {
+                    sequence: *sequence,
+                    path: self.segments[&entry.segment].path.clone(),
+                    offset: entry.offset,
+                    len: entry.len,
+                })
+            })
+            .collect()
+    }
+
+    /// Read events from their segments, skipping any no longer indexed
+    async fn read(&self, sequences: &[u64]) -> Result<Vec<StoredEvent>> {
+        read_located(&self.locate(sequences)).await
+    }
+
+    async fn latest(&self, aggregate_id: &str) -> Result<Option<StoredEvent>> {
+        let sequences = self.sequences_of(aggregate_id);
+        Ok(self.read(&sequences[sequences.len().saturating_sub(1)..]).await?.pop())
+    }
+}
+
+/// Event store persisted in append-only segment files in one directory.
+///
+/// Only one process may open a directory at a time. Message IDs are
+/// remembered for as long as their events are kept.
+#[derive(Clone)]
+pub struct FileEventStore {
+    dir: PathBuf,
+    state: Arc<RwLock<LogState>>,
+    cid_policy: CidPolicy,
+    fsync: FsyncPolicy,
+    segment_size: u64,
+}
+
+impl FileEventStore {
+    /// Open or create a store in `dir`, recovering its index from the segments
+    pub async fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&dir).await.map_err(io_error)?;
+
+        let mut paths = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await.map_err(io_error)?;
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+            if let Some(first) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<u64>().ok()) {
+                paths.push((first, path));
+            }
+        }
+        paths.sort();
+
+        let mut records = Vec::new();
+        let mut segments = BTreeMap::new();
+        for (n, (first, path)) in paths.iter().enumerate() {
+            let is_last = n + 1 == paths.len();
+            let (segment_records, len) = recover_segment(path, is_last).await?;
+            records.extend(segment_records.into_iter().map(|(record, offset, len)| (*first, record, offset, len)));
+            segments.insert(
+                *first,
+                Segment {
+                    path: path.clone(),
+                    len,
+                    live: 0,
+                },
+            );
+        }
+
+        let last_sequence = paths.last().map_or(0, |(first, _)| first.saturating_sub(1));
+        if segments.is_empty() {
+            let path = segment_path(&dir, 1);
+            File::create(&path).await.map_err(io_error)?;
+            segments.insert(1, Segment { path, len: 0, live: 0 });
+        }
+
+        let active = *segments.keys().next_back().expect("a segment was just ensured");
+        let writer = OpenOptions::new()
+            .append(true)
+            .open(&segments[&active].path)
+            .await
+            .map_err(io_error)?;
+
+        let mut state = LogState {
+            segments,
+            writer,
+            unsynced: 0,
+            index: BTreeMap::new(),
+            aggregates: HashMap::new(),
+            seen: HashMap::new(),
+            last_sequence,
+            subscribers: Vec::new(),
+            failed: None,
+        };
+        for (segment, record, offset, len) in records {
+            match record {
+                Record::Event(event) => {
+                    // Only the tail can have been left half written
+                    if segment == active {
+                        event.verified_cid()?;
+                    }
+                    state.insert(&event, segment, offset, len);
+                }
+                Record::Purge {
+                    aggregate_id,
+                    before_sequence,
+                    last_sequence,
+                } => {
+                    state.remove_prefix(&aggregate_id, before_sequence);
+                    state.last_sequence = state.last_sequence.max(last_sequence);
+                }
+            }
+        }
+
+        let store = Self {
+            dir,
+            state: Arc::new(RwLock::new(state)),
+            cid_policy: CidPolicy::default(),
+            fsync: FsyncPolicy::Always,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+        };
+        store.remove_dead_segments(&mut *store.state.write().await).await?;
+
+        Ok(store)
+    }
+
+    /// When appends are synced to disk; `Always` by default
+    pub fn with_fsync_policy(mut self, policy: FsyncPolicy) -> Self {
+        self.fsync = policy;
+        self
+    }
+
+    /// Size at which a new segment is started
+    pub fn with_segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    /// Hash function and codec used for new event CIDs
+    pub fn with_cid_policy(mut self, policy: CidPolicy) -> Self {
+        self.cid_policy = policy;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Flush every acknowledged append to disk, whatever the fsync policy
+    pub async fn sync(&self) -> Result<()> {
+        let mut state = self.state.write().await;
+        self.sync_writer(&mut state).await
+    }
+
+    /// Sync the active segment. A record that was written but could not be
+    /// synced may or may not survive, so the store refuses further writes
+    /// until it is reopened and recovers whatever reached the disk.
+    async fn sync_writer(&self, state: &mut LogState) -> Result<()> {
+        if let Err(e) = state.writer.sync_data().await {
+            let active = state.active_segment();
+            state.failed = Some(format!(
+                "{} could not be synced, reopen the store: {}",
+                state.segments[&active].path.display(),
+                e
+            ));
+            return Err(io_error(e));
+        }
+        state.unsynced = 0;
+        Ok(())
+    }
+
+    /// Frame and append a record to the active segment, starting a new one
+    /// first if it is full. Returns the segment, offset and length of the
+    /// record's payload.
+    async fn write(&self, state: &mut LogState, record: &Record) -> Result<(u64, u64, u32)> {
+        if let Some(reason) = &state.failed {
+            return Err(EventStoreError::Storage(reason.clone()));
+        }
+        
+        let payload = serde_json::to_vec(record)?;
+        let len = u32::try_from(payload.len())
+            .map_err(|_| EventStoreError::Storage(format!("Record of {} bytes is too large", payload.len())))?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend(len.to_le_bytes());
+        frame.extend(checksum(&payload).to_le_bytes());
+        frame.extend(&payload);
+
+        // Segments are named after the first sequence they may hold
+        let active = state.active_segment();
+        let next_sequence = state.last_sequence + 1;
+        let used = state.segments[&active].len;
+        if used > 0 && used + frame.len() as u64 > self.segment_size && next_sequence > active {
+            self.roll(state, next_sequence).await?;
+        }
+
+        let active = state.active_segment();
+        let segment = state.segments.get_mut(&active).expect("the active segment is indexed");
+        let offset = segment.len + FRAME_HEADER_LEN as u64;
+        if let Err(e) = write_frame(&mut state.writer, &frame).await {
+            // Cut off whatever part of the frame was written, or every later
+            // record would follow a torn one and be lost on recovery
+            if let Err(truncate) = state.writer.set_len(segment.len).await {
+                state.failed = Some(format!(
+                    "{} could not be truncated after a failed write, reopen the store: {}",
+                    segment.path.display(),
+                    truncate
+                ));
+            }
+            return Err(io_error(e));
+        }
+        segment.len += frame.len() as u64;
+
+        Ok((active, offset, len))
+    }
+
+    /// Seal the active segment and start a new one named after `first_sequence`
+    async fn roll(&self, state: &mut LogState, first_sequence: u64) -> Result<()> {
+        self.sync_writer(state).await?;
+
+        let path = segment_path(&self.dir, first_sequence);
+        state.writer = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(io_error)?;
+        state.segments.insert(first_sequence, Segment { path, len: 0, live: 0 });
+        sync_dir(&self.dir).await
+    }
+
+    async fn sync_per_policy(&self, state: &mut LogState) -> Result<()> {
+        state.unsynced += 1;
+        let due = match self.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Every(n) => state.unsynced >= n,
+            FsyncPolicy::Never => false,
+        };
+        if due {
+            self.sync_writer(state).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Assign a sequence, persist the event, index it and notify subscribers
+    async fn commit(&self, state: &mut LogState, mut event: StoredEvent, cid: Cid) -> Result<EventMetadata> {
+        event.sequence = state.last_sequence + 1;
+
+        let (segment, offset, len) = self.write(state, &Record::Event(Box::new(event.clone()))).await?;
+        self.sync_per_policy(state).await?;
+        state.insert(&event, segment, offset, len);
+
+        state.subscribers.retain(|subscriber| {
+            !subscriber.scope.matches(&event) || subscriber.sender.unbounded_send(event.clone()).is_ok()
+        });
+
+        Ok(EventMetadata {
+            sequence: event.sequence,
+            cid: Some(cid),
+            timestamp: event.timestamp,
+            duplicate: false,
+        })
+    }
+
+    /// Delete leading segments that hold no live events; the active segment
+    /// is always kept
+    async fn remove_dead_segments(&self, state: &mut LogState) -> Result<()> {
+        let active = state.active_segment();
+        let dead: Vec<u64> = state
+            .segments
+            .iter()
+            .take_while(|(first, segment)| **first != active && segment.live == 0)
+            .map(|(first, _)| *first)
+            .collect();
+        if dead.is_empty() {
+            return Ok(());
+        }
+
+        for first in dead {
+            if let Some(segment) = state.segments.remove(&first) {
+                tokio::fs::remove_file(&segment.path).await.map_err(io_error)?;
+            }
+        }
+        sync_dir(&self.dir).await
+    }
+
+    async fn read_where(&self, from_sequence: u64, limit: usize, matches: impl Fn(&IndexEntry) -> bool) -> Result<Vec<StoredEvent>> {
+        let state = self.state.read().await;
+        let mut sequences = state.select(from_sequence, matches);
+        sequences.truncate(limit);
+        state.read(&sequences).await
+    }
+}
+
+#[async_trait]
+impl EventStore for FileEventStore {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        let mut stored_event = StoredEvent {
+            sequence: 0,
+            aggregate_id: event.aggregate_id,
+            aggregate_type: Some(event.aggregate_type),
+            event_type: event.event_type,
+            event_data: event.event_data,
+            payload_cid: None,
+            header: event.header,
+            cid: None,
+            parent_cid: parent_cid.map(|c| c.to_string()),
+            timestamp: chrono::Utc::now(),
+        };
+        let cid = self.cid_policy.cid(&stored_event.canonical_bytes()?);
+        stored_event.cid = Some(cid.to_string());
+
+        let mut state = self.state.write().await;
+        if let Some(receipt) = state.seen.get(&stored_event.header.message_id) {
+            return Ok(receipt.clone());
+        }
+        let latest = state.latest(&stored_event.aggregate_id).await?;
+        check_append(latest.as_ref(), &stored_event, parent_cid.as_ref())?;
+
+        self.commit(&mut state, stored_event, cid).await
+    }
+
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        let cid = event.verified_cid()?;
+        let parent_cid = event.parent_cid_value()?;
+
+        let mut state = self.state.write().await;
+        if let Some(receipt) = state.seen.get(&event.header.message_id) {
+            return Ok(receipt.clone());
+        }
+        let latest = state.latest(&event.aggregate_id).await?;
+        check_append(latest.as_ref(), &event, parent_cid.as_ref())?;
+
+        self.commit(&mut state, event, cid).await
+    }
+
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        // Look up one event at a time, resuming after the last sequence
+        // yielded, so appends and purges during the read are respected
+        let state = self.state.clone();
+        let aggregate_id = aggregate_id.to_string();
+
+        let events = futures::stream::try_unfold(from_sequence, move |from| {
+            let state = state.clone();
+            let aggregate_id = aggregate_id.clone();
+            async move {
+                let state = state.read().await;
+                let sequences = state.sequences_of(&aggregate_id);
+                let start = sequences.partition_point(|sequence| *sequence < from);
+                let Some(event) = state.read(&sequences[start..(start + 1).min(sequences.len())]).await?.pop() else {
+                    return Ok(None);
+                };
+                let next = event.sequence + 1;
+                Ok::<_, EventStoreError>(Some((event, next)))
+            }
+        });
+
+        Ok(Box::pin(events))
+    }
+
+    async fn get_events(
+        &self,
+        aggregate_id: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        let state = self.state.read().await;
+        let sequences = state.sequences_of(aggregate_id);
+        let start = sequences.partition_point(|sequence| *sequence < from_sequence);
+        let end = start.saturating_add(limit).min(sequences.len());
+        state.read(&sequences[start..end]).await
+    }
+
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        let state = self.state.read().await;
+        let sequences: Vec<u64> = state
+            .sequences_of(aggregate_id)
+            .iter()
+            .rev()
+            .filter(|sequence| from_sequence.is_none_or(|from| **sequence <= from))
+            .take(limit)
+            .copied()
+            .collect();
+        state.read(&sequences).await
+    }
+
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        let events = self
+            .read_where(0, usize::MAX, |e| e.matches(&scope) && e.timestamp >= from && e.timestamp < to)
+            .await?;
+        Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+    }
+
+    async fn get_events_by_correlation(
+        &self,
+        correlation_id: &str,
+    ) -> Result<Vec<StoredEvent>> {
+        // Correlation IDs are not indexed, so this reads the whole log
+        let mut events = self.read_where(0, usize::MAX, |_| true).await?;
+        events.retain(|e| e.header.correlation_id == correlation_id);
+        Ok(events)
+    }
+
+    async fn read_category(
+        &self,
+        aggregate_type: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.read_where(from_sequence, limit, |e| e.category == aggregate_type).await
+    }
+
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        let mut state = self.state.write().await;
+        let sequences = state.sequences_of(aggregate_id);
+        if sequences.partition_point(|sequence| *sequence < before_sequence) == 0 {
+            return Ok(0);
+        }
+
+        // The purge must be durable before any segment it empties is deleted
+        let record = Record::Purge {
+            aggregate_id: aggregate_id.to_string(),
+            before_sequence,
+            last_sequence: state.last_sequence,
+        };
+        self.write(&mut state, &record).await?;
+        self.sync_writer(&mut state).await?;
+
+        let removed = state.remove_prefix(aggregate_id, before_sequence);
+        self.remove_dead_segments(&mut state).await?;
+
+        Ok(removed)
+    }
+
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        let state = self.state.read().await;
+
+        let mut streams: HashMap<AggregateKey, &[u64]> = HashMap::new();
+        for (aggregate_id, sequences) in &state.aggregates {
+            let Some(entry) = sequences.first().and_then(|sequence| state.index.get(sequence)) else {
+                continue;
+            };
+            streams.insert(AggregateKey::new(&entry.category, aggregate_id), sequences);
+        }
+
+        let (keys, next) = query.page(streams.keys().cloned().collect())?;
+        let mut aggregates = Vec::with_capacity(keys.len());
+        for key in keys {
+            let sequences = streams[&key];
+            let (first, last) = (sequences[0], sequences[sequences.len() - 1]);
+            let head = state.read(&[last]).await?.pop();
+            aggregates.push(AggregateInfo {
+                aggregate_id: key.aggregate_id,
+                aggregate_type: key.aggregate_type,
+                first_sequence: first,
+                last_sequence: last,
+                event_count: sequences.len() as u64,
+                head_cid: head.as_ref().and_then(|e| e.cid.clone()),
+                created_at: state.index[&first].timestamp,
+                updated_at: state.index[&last].timestamp,
+            });
+        }
+
+        Ok(AggregatePage { aggregates, next })
+    }
+
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        // Look up history and register under one lock, so no append can fall
+        // between the two, but read the history after releasing it
+        let history = {
+            let mut state = self.state.write().await;
+            let mut history = state.select(0, |e| e.matches(&scope));
+            match from {
+                SubscribeFrom::Beginning => {}
+                SubscribeFrom::Sequence(sequence) => history.retain(|s| *s >= sequence),
+                SubscribeFrom::Timestamp(timestamp) => history.retain(|s| state.index[s].timestamp >= timestamp),
+                SubscribeFrom::Last => history = history.pop().into_iter().collect(),
+                SubscribeFrom::New => history.clear(),
+            }
+            state.subscribers.push(Subscriber { scope, sender });
+            state.locate(&history)
+        };
+        let history = read_located(&history).await?;
+
+        // Live events already delivered as history are dropped
+        let last = history.last().map_or(0, |event| event.sequence);
+        let live = receiver.filter(move |event| futures::future::ready(event.sequence > last));
+
+        Ok(Box::new(futures::stream::iter(history).chain(live)))
+    }
+}
+
+/// Read events from their records.
+///
+/// Consecutive events mostly share a segment, so it is kept open between
+/// them. A segment deleted since the events were located only held purged
+/// events, which are skipped.
+async fn read_located(locations: &[Location]) -> Result<Vec<StoredEvent>> {
+    let mut events = Vec::with_capacity(locations.len());
+    let mut open: Option<(&Path, File)> = None;
+
+    for location in locations {
+        let mut file = match open.take() {
+            Some((path, file)) if path == location.path => file,
+            _ => match File::open(&location.path).await {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(io_error(e)),
+            },
+        };
+        file.seek(SeekFrom::Start(location.offset)).await.map_err(io_error)?;
+        let mut payload = vec![0; location.len as usize];
+        file.read_exact(&mut payload).await.map_err(io_error)?;
+        open = Some((&location.path, file));
+
+        match serde_json::from_slice(&payload)? {
+            Record::Event(event) => events.push(*event),
+            Record::Purge { .. } => {
+                return Err(EventStoreError::Storage(format!(
+                    "Sequence {} indexes a purge record",
+                    location.sequence
+                )))
+            }
+        }
+    }
+
+    Ok(events)
+}
+
+/// Write a whole frame to the active segment
+async fn write_frame(writer: &mut File, frame: &[u8]) -> std::io::Result<()> {
+    writer.write_all(frame).await?;
+    writer.flush().await
+}
+
+/// Read a segment's records, with the offset and length of each payload.
+///
+/// A torn or corrupt record at the end of the last segment is what a crash
+/// mid-append leaves behind, so it is cut off; anywhere else it is an error.
+async fn recover_segment(path: &Path, is_last: bool) -> Result<(Vec<(Record, u64, u32)>, u64)> {
+    let bytes = tokio::fs::read(path).await.map_err(io_error)?;
+
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        match parse_frame(&bytes[offset..]) {
+            Some(payload) => {
+                let record = serde_json::from_slice(payload)?;
+                records.push((record, (offset + FRAME_HEADER_LEN) as u64, payload.len() as u32));
+                offset += FRAME_HEADER_LEN + payload.len();
+            }
+            None if is_last => {
+                tracing::warn!("Truncating {} at offset {}: incomplete record", path.display(), offset);
+                let file = OpenOptions::new().write(true).open(path).await.map_err(io_error)?;
+                file.set_len(offset as u64).await.map_err(io_error)?;
+                file.sync_all().await.map_err(io_error)?;
+                break;
+            }
+            None => {
+                return Err(EventStoreError::Storage(format!(
+                    "Corrupt record in {} at offset {}",
+                    path.display(),
+                    offset
+                )))
+            }
+        }
+    }
+
+    Ok((records, offset as u64))
+}
+
+/// Payload of the frame at the start of `bytes`, if it is complete and its
+/// checksum matches
+fn parse_frame(bytes: &[u8]) -> Option<&[u8]> {
+    let header = bytes.get(..FRAME_HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[..4].try_into().ok()?) as usize;
+    let expected = u64::from_le_bytes(header[4..].try_into().ok()?);
+
+    let payload = bytes.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN.checked_add(len)?)?;
+    (checksum(payload) == expected).then_some(payload)
+}
+
+fn checksum(payload: &[u8]) -> u64 {
+    let hash = blake3::hash(payload);
+    u64::from_le_bytes(hash.as_bytes()[..8].try_into().expect("blake3 hashes are 32 bytes"))
+}
+
+fn segment_path(dir: &Path, first_sequence: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", first_sequence, SEGMENT_EXTENSION))
+}
+
+/// Make segment creation and deletion durable
+async fn sync_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    File::open(dir).await.map_err(io_error)?.sync_all().await.map_err(io_error)?;
+    #[cfg(not(unix))]
+    let _ = dir;
+
+    Ok(())
+}
+
+fn io_error(e: std::io::Error) -> EventStoreError {
+    EventStoreError::Storage(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("cim-events-{}", uuid::Uuid::new_v4()))
+    }
+
+    async fn append_lines(store: &FileEventStore, aggregate_id: &str, count: usize) -> Option<Cid> {
+        let mut parent = store.read_backward(aggregate_id, None, 1).await.unwrap().pop().map(|e| e.verified_cid().unwrap());
+        for n in 0..count {
+            let event = NewEvent::new(aggregate_id, "LineAdded", json!({ "line": n })).with_aggregate_type("Order");
+            parent = store.append(event, parent).await.unwrap().cid;
+        }
+        parent
+    }
+
+    #[tokio::test]
+    async fn reopening_recovers_the_index_and_chain() {
+        let dir = temp_dir();
+        let store = FileEventStore::open(&dir).await.unwrap().with_segment_size(512);
+        let head = append_lines(&store, "order-1", 5).await;
+        append_lines(&store, "order-2", 2).await;
+        drop(store);
+
+        let store = FileEventStore::open(&dir).await.unwrap();
+        let events = store.get_events("order-1", 0, 10).await.unwrap();
+
+        assert!(std::fs::read_dir(&dir).unwrap().count() > 1);
+        assert_eq!(events.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+        let next = NewEvent::new("order-1", "LineAdded", json!({})).with_aggregate_type("Order");
+        assert_eq!(store.append(next, head).await.unwrap().sequence, 8);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_torn_tail_is_cut_off_on_recovery() {
+        let dir = temp_dir();
+        let store = FileEventStore::open(&dir).await.unwrap();
+        append_lines(&store, "order-1", 3).await;
+        drop(store);
+
+        // Simulate a crash halfway through writing a record
+        let path = segment_path(&dir, 1);
+        let len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(len - 10).unwrap();
+
+        let store = FileEventStore::open(&dir).await.unwrap();
+        append_lines(&store, "order-1", 1).await;
+
+        let events = store.get_events("order-1", 0, 10).await.unwrap();
+        assert_eq!(events.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn a_failed_sync_refuses_writes_until_reopened() {
+        let dir = temp_dir();
+        let store = FileEventStore::open(&dir).await.unwrap();
+        append_lines(&store, "order-1", 1).await;
+
+        // Syncing /dev/null fails, as a disk error would
+        store.state.write().await.writer = OpenOptions::new().append(true).open("/dev/null").await.unwrap();
+        let unsynced = store.append(NewEvent::new("order-2", "LineAdded", json!({})), None).await;
+        let refused = store.append(NewEvent::new("order-3", "LineAdded", json!({})), None).await;
+
+        assert!(unsynced.is_err());
+        assert!(matches!(refused, Err(EventStoreError::Storage(_))));
+        drop(store);
+
+        let store = FileEventStore::open(&dir).await.unwrap();
+        append_lines(&store, "order-3", 1).await;
+        assert_eq!(store.get_events("order-3", 0, 1).await.unwrap()[0].sequence, 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn purges_delete_emptied_segments_and_survive_reopening() {
+        let dir = temp_dir();
+        let store = FileEventStore::open(&dir).await.unwrap().with_segment_size(512);
+        append_lines(&store, "order-1", 6).await;
+        append_lines(&store, "order-2", 1).await;
+        let segments = std::fs::read_dir(&dir).unwrap().count();
+
+        assert_eq!(store.purge("order-1", 7).await.unwrap(), 6);
+        assert!(std::fs::read_dir(&dir).unwrap().count() < segments);
+        drop(store);
+
+        let store = FileEventStore::open(&dir).await.unwrap();
+        assert!(store.get_events("order-1", 0, 10).await.unwrap().is_empty());
+        assert_eq!(store.get_events("order-2", 0, 10).await.unwrap()[0].sequence, 7);
+
+        // Sequences of purged events are never handed out again
+        store.purge("order-2", 8).await.unwrap();
+        drop(store);
+        let store = FileEventStore::open(&dir).await.unwrap();
+        append_lines(&store, "order-3", 1).await;
+        assert_eq!(store.get_events("order-3", 0, 1).await.unwrap()[0].sequence, 8);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
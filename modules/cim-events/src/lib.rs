@@ -4,15 +4,31 @@
 //! 
 //! This module provides:
 //! - Event sourcing with NATS JetStream persistence
-//! - CID chain validation for event integrity
+//! - CID chain validation for event integrity, with pluggable hash functions
 //! - Correlation and causation ID tracking
-//! - Real-time event subscriptions
+//! - Real-time event subscriptions, per aggregate or per aggregate type
 //! - Optimistic concurrency control
+//! - Object-safe store trait for runtime backend selection (`Arc<dyn EventStore>`)
+//! - Idempotent appends keyed by message ID
+//! - Transparent payload compression (zstd, lz4)
+//! - Claim-check storage of oversized payloads in content-addressed stores
+//! - CAR archive export and import of verified histories
+//! - Time-travel rehydration of aggregates as of a version or timestamp
+//! - Per-event state diffs for debugging aggregates
+//! - Tenant and domain scoped subject namespaces
+//! - Paginated listing of aggregates with their stream metadata
+//! - Aggregate tombstones, archiving and checkpointed truncation
+//! - Retention rules per event type and category, with snapshot compaction
+//! - Retries with backoff and circuit breaking for unreliable connections
+//! - Offline appends buffered in a local write-ahead log and replayed on reconnect
+//! - Native append-only segment file backend for deployments without NATS
+//! - SQLite backend for single-file stores inspectable with SQL (`sqlite` feature)
+//! - PostgreSQL backend sharing transactions with read models (`postgres` feature)
 //! 
 //! ## Example
 //! 
 //! ```rust,no_run
-//! use cim_events::event_store::{EventStore, JetStreamEventStore};
+//! use cim_events::event_store::{EventStoreExt, JetStreamEventStore};
 //! use cim_events::domain::{Event, EventHeader};
 //! 
 //! #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -52,21 +68,66 @@
 //! }
 //! ```
 
+pub mod admin;
+pub mod car;
+pub mod catalog;
+pub mod cid_policy;
+pub mod compression;
+pub mod config;
+pub mod content_store;
+pub mod diff;
 pub mod domain;
 pub mod event_store;
+pub mod file_store;
+pub mod lifecycle;
+pub mod memory;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+pub mod rehydrate;
+pub mod resilience;
+pub mod retention;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod subject;
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+mod sql;
+#[cfg(test)]
+mod testing;
+pub mod wal;
 
 // Re-export commonly used types
-pub use domain::{Event, EventHeader, EventEnvelope, EventSourced, Command};
-pub use event_store::{EventStore, JetStreamEventStore, StoredEvent, EventMetadata};
+pub use domain::{Event, EventHeader, EventEnvelope, EventSourced, Command, ExpectedVersion};
+pub use event_store::{EventStore, EventStoreExt, EventScope, JetStreamEventStore, NewEvent, ReadStream, StoredEvent, EventMetadata, SubscribeFrom};
+pub use car::{CarArchive, CarVersion};
+pub use cid_policy::{CidCodec, CidPolicy, HashAlgorithm};
+pub use compression::{Codec, CompressionPolicy};
+pub use config::{EventStoreConfig, Placement};
+pub use content_store::{
+    ContentStore, FileSystemContentStore, InMemoryContentStore, ObjectStoreContentStore,
+};
+#[cfg(feature = "ipfs")]
+pub use content_store::IpfsContentStore;
+pub use memory::InMemoryEventStore;
+pub use file_store::{FileEventStore, FsyncPolicy};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteEventStore;
+#[cfg(feature = "postgres")]
+pub use postgres::PostgresEventStore;
+pub use rehydrate::{load_as_of, replay_steps, AsOf, ReplayStep};
+pub use diff::{explain_history, DiffOp, StateChange};
+pub use subject::SubjectNamespace;
+pub use catalog::{AggregateInfo, AggregatePage, AggregateQuery};
+pub use lifecycle::{archive, tombstone, truncate, Checkpoint, Snapshot, Truncation};
+pub use retention::{compact, compact_category, load_compacted, Retain, RetentionRules};
+pub use resilience::{CircuitBreaker, CircuitState, ResilientEventStore, RetryPolicy};
+pub use wal::{BufferedEventStore, SyncReport, WalEntry};
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-    
     #[test]
     fn module_exports_work() {
         // Verify that our re-exports are accessible
-        use crate::{Event, EventHeader, EventStore};
+        use crate::EventHeader;
         
         // This test just ensures the module structure is correct
         assert_eq!(std::mem::size_of::<EventHeader>(), std::mem::size_of::<EventHeader>());
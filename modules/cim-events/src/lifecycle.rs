@@ -0,0 +1,246 @@
+//! Closing, truncating and archiving aggregate streams.
+//!
+//! A tombstone is a terminal event: once it is an aggregate's latest event,
+//! the store rejects further appends. Truncation appends a checkpoint that
+//! records the CID of the last event it removes, then purges the prefix, so
+//! the chain of the remaining events still verifies. Archiving exports the
+//! whole history to a content store before truncating it away.
+
+use cid::Cid;
+use serde::{Deserialize, Serialize};
+
+use crate::car::{export_aggregate, CarVersion};
+use crate::cid_policy::CidPolicy;
+use crate::content_store::ContentStore;
+use crate::event_store::{EventMetadata, EventStore, EventStoreError, NewEvent, Result, StoredEvent};
+
+/// Event type of the terminal event that closes an aggregate
+pub const TOMBSTONE_EVENT_TYPE: &str = "AggregateTombstoned";
+
+/// Event type of the checkpoint written before a truncation
+pub const CHECKPOINT_EVENT_TYPE: &str = "ChainCheckpoint";
+
+/// Payload of a checkpoint event
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// Events stored before this sequence were removed
+    pub truncated_before: u64,
+
+    /// CID of the last removed event, which the first kept event links to
+    pub prefix_head_cid: String,
+
+    /// CID of the CAR archive holding the removed events, if they were archived
+    pub archive_cid: Option<String>,
+
+    /// Whether the aggregate was closed when it was truncated
+    pub closed: bool,
+
+    /// Aggregate state built from the removed events, if it was captured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<Snapshot>,
+}
+
+/// Serialized aggregate state at a point in its history
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Aggregate version the state was captured at
+    pub version: u64,
+
+    pub state: serde_json::Value,
+}
+
+impl Checkpoint {
+    /// The checkpoint an event carries, if it is one
+    pub fn from_event(event: &StoredEvent) -> Option<Self> {
+        if event.event_type != CHECKPOINT_EVENT_TYPE {
+            return None;
+        }
+        serde_json::from_value(event.event_data.clone()).ok()
+    }
+}
+
+/// Outcome of a truncation
+#[derive(Debug, Clone)]
+pub struct Truncation {
+    /// Receipt of the checkpoint event
+    pub checkpoint: EventMetadata,
+
+    /// Number of events purged
+    pub removed: u64,
+
+    /// CID of the archive the events were exported to, if any
+    pub archive_cid: Option<Cid>,
+}
+
+/// Whether an event is written by the store's lifecycle operations rather
+/// than by the aggregate itself
+pub fn is_lifecycle_event(event: &StoredEvent) -> bool {
+    event.event_type == TOMBSTONE_EVENT_TYPE || event.event_type == CHECKPOINT_EVENT_TYPE
+}
+
+/// Reject appending `event_type` after `latest` if that closed the aggregate.
+///
+/// Checkpoints are still accepted, so closed aggregates can be truncated.
+pub(crate) fn ensure_open(latest: Option<&StoredEvent>, event_type: &str) -> Result<()> {
+    let Some(latest) = latest else {
+        return Ok(());
+    };
+
+    if closes(latest) && event_type != CHECKPOINT_EVENT_TYPE {
+        return Err(EventStoreError::AggregateClosed(latest.aggregate_id.clone()));
+    }
+
+    Ok(())
+}
+
+/// Whether an aggregate is closed once `event` is its latest event
+fn closes(event: &StoredEvent) -> bool {
+    event.event_type == TOMBSTONE_EVENT_TYPE || Checkpoint::from_event(event).is_some_and(|checkpoint| checkpoint.closed)
+}
+
+/// Close an aggregate with a terminal event; later appends are rejected
+pub async fn tombstone<S: EventStore + ?Sized>(store: &S, aggregate_id: &str, reason: &str) -> Result<EventMetadata> {
+    let head = head(store, aggregate_id).await?;
+    let event = NewEvent::new(aggregate_id, TOMBSTONE_EVENT_TYPE, serde_json::json!({ "reason": reason }))
+        .with_aggregate_type(head.category());
+
+    store.append(event, Some(head.verified_cid()?)).await
+}
+
+/// Remove an aggregate's events stored before `before_sequence`, keeping at
+/// least a checkpoint so the remaining chain still verifies.
+///
+/// No snapshot is taken, so the removed events' effect on the aggregate's
+/// state is lost; [`crate::retention::compact`] keeps it.
+pub async fn truncate<S: EventStore + ?Sized>(store: &S, aggregate_id: &str, before_sequence: u64) -> Result<Truncation> {
+    let head = head(store, aggregate_id).await?;
+    truncate_at(store, head, before_sequence, None, None).await
+}
+
+/// Export an aggregate's whole history as a CAR archive into `content_store`,
+/// then purge it, leaving only a checkpoint that records the archive's CID
+pub async fn archive<S: EventStore + ?Sized>(
+    store: &S,
+    aggregate_id: &str,
+    content_store: &dyn ContentStore,
+) -> Result<Truncation> {
+    let head = head(store, aggregate_id).await?;
+
+    let bytes = export_aggregate(store, aggregate_id).await?.to_bytes(CarVersion::V2)?;
+    let archive_cid = CidPolicy::default().cid(&bytes);
+    content_store.put(&archive_cid, &bytes).await?;
+
+    let before_sequence = head.sequence + 1;
+    truncate_at(store, head, before_sequence, Some(archive_cid), None).await
+}
+
+/// Checkpoint on top of `head` and purge what precedes `before_sequence`.
+///
+/// The checkpoint names `head` as its parent, so it is rejected if anything
+/// was appended since `head` was read and would otherwise be purged unseen.
+pub(crate) async fn truncate_at<S: EventStore + ?Sized>(
+    store: &S,
+    head: StoredEvent,
+    before_sequence: u64,
+    archive_cid: Option<Cid>,
+    snapshot: Option<Snapshot>,
+) -> Result<Truncation> {
+    // Never purge the checkpoint itself
+    let before_sequence = before_sequence.min(head.sequence + 1);
+    let last_removed = store
+        .read_backward(&head.aggregate_id, Some(before_sequence.saturating_sub(1)), 1)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| EventStoreError::EventNotFound(format!("No events of {} before sequence {}", head.aggregate_id, before_sequence)))?;
+
+    let checkpoint = Checkpoint {
+        truncated_before: before_sequence,
+        prefix_head_cid: last_removed.verified_cid()?.to_string(),
+        archive_cid: archive_cid.map(|cid| cid.to_string()),
+        closed: closes(&head),
+        snapshot,
+    };
+    let event = NewEvent::new(&head.aggregate_id, CHECKPOINT_EVENT_TYPE, serde_json::to_value(&checkpoint)?)
+        .with_aggregate_type(head.category());
+    let receipt = store.append(event, Some(head.verified_cid()?)).await?;
+
+    let removed = store.purge(&head.aggregate_id, before_sequence).await?;
+
+    Ok(Truncation {
+        checkpoint: receipt,
+        removed,
+        archive_cid,
+    })
+}
+
+pub(crate) async fn head<S: EventStore + ?Sized>(store: &S, aggregate_id: &str) -> Result<StoredEvent> {
+    store
+        .read_backward(aggregate_id, None, 1)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| EventStoreError::EventNotFound(format!("Aggregate {} has no events", aggregate_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::content_store::InMemoryContentStore;
+    use crate::memory::InMemoryEventStore;
+    use serde_json::json;
+
+    async fn store_with_history(count: usize) -> InMemoryEventStore {
+        let store = InMemoryEventStore::new();
+        let mut parent = None;
+        for n in 0..count {
+            let event = NewEvent::new("order-1", "LineAdded", json!({ "line": n })).with_aggregate_type("Order");
+            parent = store.append(event, parent).await.unwrap().cid;
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn tombstoned_aggregates_reject_appends() {
+        let store = store_with_history(2).await;
+
+        tombstone(&store, "order-1", "customer left").await.unwrap();
+        let result = store.append(NewEvent::new("order-1", "LineAdded", json!({})), None).await;
+
+        assert!(matches!(result, Err(EventStoreError::AggregateClosed(_))));
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn truncated_chains_still_verify() {
+        let store = store_with_history(5).await;
+
+        let truncation = truncate(&store, "order-1", 4).await.unwrap();
+        let events = store.get_events("order-1", 0, 10).await.unwrap();
+
+        assert_eq!(truncation.removed, 3);
+        assert_eq!(events.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![4, 5, 6]);
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+
+        // A purge the checkpoint does not describe leaves a dangling parent
+        store.purge("order-1", 6).await.unwrap();
+        assert!(!store.validate_cid_chain("order-1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn archives_hold_the_purged_history() {
+        let store = store_with_history(3).await;
+        let content_store = InMemoryContentStore::new();
+        tombstone(&store, "order-1", "closed").await.unwrap();
+
+        let truncation = archive(&store, "order-1", &content_store).await.unwrap();
+        let archived = content_store.get(&truncation.archive_cid.unwrap()).await.unwrap();
+        let events = store.get_events("order-1", 0, 10).await.unwrap();
+
+        assert_eq!(crate::car::CarArchive::from_bytes(&archived).unwrap().blocks.len(), 4);
+        assert_eq!(events.len(), 1);
+        assert!(Checkpoint::from_event(&events[0]).unwrap().closed);
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+        assert!(store.append(NewEvent::new("order-1", "LineAdded", json!({})), None).await.is_err());
+    }
+}
@@ -0,0 +1,541 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use cid::Cid;
+use futures::channel::mpsc;
+use futures::Stream;
+use tokio::sync::RwLock;
+
+use crate::catalog::{AggregateInfo, AggregateKey, AggregatePage, AggregateQuery};
+use crate::cid_policy::CidPolicy;
+use crate::event_store::{
+    check_append, EventMetadata, EventScope, EventStore, NewEvent, ReadStream, Result,
+    StoredEvent, SubscribeFrom,
+};
+
+/// How long the in-memory store remembers message IDs by default
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// In-memory event store for testing and development.
+///
+/// Message IDs are remembered for the idempotency window, so a retried append
+/// returns the original receipt rather than storing the event twice.
+#[derive(Clone)]
+pub struct InMemoryEventStore {
+    state: Arc<RwLock<MemoryState>>,
+    cid_policy: CidPolicy,
+    idempotency_window: Duration,
+}
+
+#[derive(Default)]
+struct MemoryState {
+    events: Vec<StoredEvent>,
+    last_sequence: u64,
+    seen: HashMap<String, SeenMessage>,
+    /// Message IDs in the order they were recorded, so expired ones are
+    /// dropped from the front without scanning the rest
+    expiry: VecDeque<(Instant, String)>,
+    subscribers: Vec<Subscriber>,
+}
+
+/// Receipt of a stored message ID
+struct SeenMessage {
+    sequence: u64,
+    cid: Option<Cid>,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    recorded_at: Instant,
+}
+
+struct Subscriber {
+    scope: EventScope,
+    sender: mpsc::UnboundedSender<StoredEvent>,
+}
+
+impl MemoryState {
+    /// Receipt for a message ID stored within the window, if any
+    fn duplicate(&mut self, message_id: &str, window: Duration) -> Option<EventMetadata> {
+        while let Some((recorded_at, expired)) = self.expiry.pop_front() {
+            if recorded_at.elapsed() < window {
+                self.expiry.push_front((recorded_at, expired));
+                break;
+            }
+            if self.seen.get(&expired).is_some_and(|seen| seen.recorded_at == recorded_at) {
+                self.seen.remove(&expired);
+            }
+        }
+
+        self.seen.get(message_id).map(|seen| EventMetadata {
+            sequence: seen.sequence,
+            cid: seen.cid,
+            timestamp: seen.timestamp,
+            duplicate: true,
+        })
+    }
+
+    /// The aggregate's most recent event
+    fn latest(&self, aggregate_id: &str) -> Option<&StoredEvent> {
+        self.events.iter().rev().find(|e| e.aggregate_id == aggregate_id)
+    }
+
+    /// Assign a sequence, remember the message ID and notify subscribers
+    fn commit(&mut self, mut event: StoredEvent, cid: Cid) -> EventMetadata {
+        self.last_sequence += 1;
+        event.sequence = self.last_sequence;
+
+        let recorded_at = Instant::now();
+        self.seen.insert(
+            event.header.message_id.clone(),
+            SeenMessage {
+                sequence: event.sequence,
+                cid: Some(cid),
+                timestamp: event.timestamp,
+                recorded_at,
+            },
+        );
+        self.expiry.push_back((recorded_at, event.header.message_id.clone()));
+        self.subscribers.retain(|subscriber| {
+            !subscriber.scope.matches(&event) || subscriber.sender.unbounded_send(event.clone()).is_ok()
+        });
+
+        let metadata = EventMetadata {
+            sequence: event.sequence,
+            cid: Some(cid),
+            timestamp: event.timestamp,
+            duplicate: false,
+        };
+        self.events.push(event);
+        metadata
+    }
+}
+
+impl InMemoryEventStore {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(RwLock::new(MemoryState::default())),
+            cid_policy: CidPolicy::default(),
+            idempotency_window: DEFAULT_IDEMPOTENCY_WINDOW,
+        }
+    }
+
+    /// How long message IDs are remembered for deduplication
+    pub fn with_idempotency_window(mut self, window: Duration) -> Self {
+        self.idempotency_window = window;
+        self
+    }
+
+    /// Hash function and codec used for new event CIDs
+    pub fn with_cid_policy(mut self, policy: CidPolicy) -> Self {
+        self.cid_policy = policy;
+        self
+    }
+
+    async fn read(&self, matches: impl Fn(&StoredEvent) -> bool, from_sequence: u64, limit: usize) -> Vec<StoredEvent> {
+        let state = self.state.read().await;
+        state
+            .events
+            .iter()
+            .filter(|e| e.sequence >= from_sequence && matches(e))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+}
+
+impl Default for InMemoryEventStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl EventStore for InMemoryEventStore {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        let mut stored_event = StoredEvent {
+            sequence: 0,
+            aggregate_id: event.aggregate_id,
+            aggregate_type: Some(event.aggregate_type),
+            event_type: event.event_type,
+            event_data: event.event_data,
+            payload_cid: None,
+            header: event.header,
+            cid: None,
+            parent_cid: parent_cid.map(|c| c.to_string()),
+            timestamp: chrono::Utc::now(),
+        };
+        let cid = self.cid_policy.cid(&stored_event.canonical_bytes()?);
+        stored_event.cid = Some(cid.to_string());
+
+        let mut state = self.state.write().await;
+        if let Some(receipt) = state.duplicate(&stored_event.header.message_id, self.idempotency_window) {
+            return Ok(receipt);
+        }
+        check_append(state.latest(&stored_event.aggregate_id), &stored_event, parent_cid.as_ref())?;
+
+        Ok(state.commit(stored_event, cid))
+    }
+
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        let cid = event.verified_cid()?;
+        let parent_cid = event.parent_cid_value()?;
+
+        let mut state = self.state.write().await;
+        if let Some(receipt) = state.duplicate(&event.header.message_id, self.idempotency_window) {
+            return Ok(receipt);
+        }
+        check_append(state.latest(&event.aggregate_id), &event, parent_cid.as_ref())?;
+
+        Ok(state.commit(event, cid))
+    }
+
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        // Walk the log one event at a time, resuming after the last sequence
+        // yielded; purges may leave gaps, so positions are found by sequence
+        let state = self.state.clone();
+        let aggregate_id = aggregate_id.to_string();
+
+        let events = futures::stream::unfold(from_sequence, move |from| {
+            let state = state.clone();
+            let aggregate_id = aggregate_id.clone();
+            async move {
+                let state = state.read().await;
+                let start = state.events.partition_point(|e| e.sequence < from);
+                let event = state.events[start..].iter().find(|e| e.aggregate_id == aggregate_id)?;
+                Some((Ok(event.clone()), event.sequence + 1))
+            }
+        });
+
+        Ok(Box::pin(events))
+    }
+
+    async fn get_events(
+        &self,
+        aggregate_id: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        Ok(self.read(|e| e.aggregate_id == aggregate_id, from_sequence, limit).await)
+    }
+
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        let state = self.state.read().await;
+        Ok(state
+            .events
+            .iter()
+            .rev()
+            .filter(|e| from_sequence.is_none_or(|from| e.sequence <= from))
+            .filter(|e| e.aggregate_id == aggregate_id)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        let events = self
+            .read(|e| scope.matches(e) && e.timestamp >= from && e.timestamp < to, 0, usize::MAX)
+            .await;
+        Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+    }
+
+    async fn get_events_by_correlation(
+        &self,
+        correlation_id: &str,
+    ) -> Result<Vec<StoredEvent>> {
+        Ok(self.read(|e| e.header.correlation_id == correlation_id, 0, usize::MAX).await)
+    }
+
+    async fn read_category(
+        &self,
+        aggregate_type: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        Ok(self.read(|e| e.category() == aggregate_type, from_sequence, limit).await)
+    }
+
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        let mut state = self.state.write().await;
+        let before = state.events.len();
+        state
+            .events
+            .retain(|e| e.aggregate_id != aggregate_id || e.sequence >= before_sequence);
+
+        Ok((before - state.events.len()) as u64)
+    }
+
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        let state = self.state.read().await;
+
+        let mut infos: HashMap<AggregateKey, AggregateInfo> = HashMap::new();
+        for event in &state.events {
+            let key = AggregateKey::new(event.category(), &event.aggregate_id);
+            let info = infos.entry(key).or_insert_with(|| AggregateInfo {
+                aggregate_id: event.aggregate_id.clone(),
+                aggregate_type: event.category().to_string(),
+                first_sequence: event.sequence,
+                last_sequence: event.sequence,
+                event_count: 0,
+                head_cid: None,
+                created_at: event.timestamp,
+                updated_at: event.timestamp,
+            });
+            info.last_sequence = event.sequence;
+            info.event_count += 1;
+            info.head_cid = event.cid.clone();
+            info.updated_at = event.timestamp;
+        }
+
+        let (keys, next) = query.page(infos.keys().cloned().collect())?;
+        let aggregates = keys.iter().filter_map(|key| infos.remove(key)).collect();
+
+        Ok(AggregatePage { aggregates, next })
+    }
+
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        // Queue history and register under one lock, so no append can fall
+        // between the two
+        let mut state = self.state.write().await;
+        let mut history = state.events.iter().filter(|e| scope.matches(e));
+        let replay: Vec<&StoredEvent> = match from {
+            SubscribeFrom::Beginning => history.collect(),
+            SubscribeFrom::Sequence(sequence) => history.filter(|e| e.sequence >= sequence).collect(),
+            SubscribeFrom::Timestamp(timestamp) => history.filter(|e| e.timestamp >= timestamp).collect(),
+            SubscribeFrom::Last => history.next_back().into_iter().collect(),
+            SubscribeFrom::New => Vec::new(),
+        };
+        for event in replay {
+            let _ = sender.unbounded_send(event.clone());
+        }
+        state.subscribers.push(Subscriber { scope, sender });
+
+        Ok(Box::new(receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{Event, EventHeader};
+    use crate::event_store::EventStoreExt;
+    use futures::StreamExt;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct OrderPlaced {
+        order_id: String,
+    }
+
+    impl Event for OrderPlaced {
+        fn event_type(&self) -> &str {
+            "OrderPlaced"
+        }
+
+        fn aggregate_id(&self) -> &str {
+            &self.order_id
+        }
+
+        fn aggregate_type(&self) -> &str {
+            "Order"
+        }
+    }
+
+    fn placed(id: &str) -> OrderPlaced {
+        OrderPlaced { order_id: id.to_string() }
+    }
+
+    #[tokio::test]
+    async fn appends_form_a_valid_chain() {
+        let store = InMemoryEventStore::new();
+
+        let first = store.append_event("order-1", placed("order-1"), None).await.unwrap();
+        store.append_event("order-1", placed("order-1"), first.cid).await.unwrap();
+
+        assert_eq!(store.get_events("order-1", 0, 10).await.unwrap().len(), 2);
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+        assert!(store.append_event("order-1", placed("order-1"), first.cid).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn repeated_message_ids_are_stored_once() {
+        let store = InMemoryEventStore::new();
+        let header = EventHeader::new().with_message_id("place-order-1");
+
+        let original = store
+            .append_event_with_header("order-1", placed("order-1"), header.clone(), None)
+            .await
+            .unwrap();
+        let retry = store
+            .append_event_with_header("order-1", placed("order-1"), header, None)
+            .await
+            .unwrap();
+
+        assert!(!original.duplicate);
+        assert!(retry.duplicate);
+        assert_eq!(retry.sequence, original.sequence);
+        assert_eq!(retry.cid, original.cid);
+        assert_eq!(store.get_events("order-1", 0, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn message_ids_expire_after_the_window() {
+        let store = InMemoryEventStore::new().with_idempotency_window(Duration::ZERO);
+        let header = EventHeader::new().with_message_id("place-order-1");
+
+        store
+            .append_event_with_header("order-1", placed("order-1"), header.clone(), None)
+            .await
+            .unwrap();
+        let second = store
+            .append_event_with_header("order-1", placed("order-1"), header, None)
+            .await
+            .unwrap();
+
+        assert!(!second.duplicate);
+        assert_eq!(store.get_events("order-1", 0, 10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn read_stream_yields_events_lazily_from_a_sequence() {
+        use futures::TryStreamExt;
+
+        let store = InMemoryEventStore::new();
+        for id in ["order-1", "order-2", "order-1", "order-1"] {
+            store.append_event(id, placed(id), None).await.unwrap();
+        }
+
+        let events: Vec<StoredEvent> = store.read_stream("order-1", 2).await.unwrap().try_collect().await.unwrap();
+        let sequences: Vec<u64> = events.iter().map(|e| e.sequence).collect();
+
+        assert_eq!(sequences, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn read_backward_returns_newest_first() {
+        let store = InMemoryEventStore::new();
+        for id in ["order-1", "order-2", "order-1", "order-1"] {
+            store.append_event(id, placed(id), None).await.unwrap();
+        }
+
+        let sequences = |events: Vec<StoredEvent>| events.iter().map(|e| e.sequence).collect::<Vec<_>>();
+
+        assert_eq!(sequences(store.read_backward("order-1", None, 2).await.unwrap()), vec![4, 3]);
+        assert_eq!(sequences(store.read_backward("order-1", Some(3), 10).await.unwrap()), vec![3, 1]);
+        assert!(store.read_backward("missing", None, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn read_between_is_limited_to_the_window_and_scope() {
+        use futures::TryStreamExt;
+
+        let store = InMemoryEventStore::new();
+        store.append_event("order-1", placed("order-1"), None).await.unwrap();
+        let from = chrono::Utc::now();
+        store.append_event("order-2", placed("order-2"), None).await.unwrap();
+        store.append_event("order-1", placed("order-1"), None).await.unwrap();
+        let to = chrono::Utc::now() + chrono::Duration::milliseconds(1);
+
+        let window: Vec<StoredEvent> = store
+            .read_between(EventScope::All, from, to)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let order_1: Vec<StoredEvent> = store
+            .read_between(EventScope::Aggregate("order-1".to_string()), from, to)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert_eq!(window.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(order_1.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn subscriptions_replay_history_then_follow_live_appends() {
+        let store = InMemoryEventStore::new();
+        for _ in 0..3 {
+            store.append_event("order-1", placed("order-1"), None).await.unwrap();
+        }
+
+        let scope = EventScope::Aggregate("order-1".to_string());
+        let mut from_two = store.subscribe_with(scope.clone(), SubscribeFrom::Sequence(2)).await.unwrap();
+        let mut last = store.subscribe_with(scope, SubscribeFrom::Last).await.unwrap();
+        store.append_event("order-1", placed("order-1"), None).await.unwrap();
+
+        for expected in [2, 3, 4] {
+            assert_eq!(from_two.next().await.unwrap().sequence, expected);
+        }
+        assert_eq!(last.next().await.unwrap().sequence, 3);
+        assert_eq!(last.next().await.unwrap().sequence, 4);
+    }
+
+    #[tokio::test]
+    async fn usable_as_a_trait_object() {
+        let store: Arc<dyn EventStore> = Arc::new(InMemoryEventStore::new());
+
+        store.append_event("order-1", placed("order-1"), None).await.unwrap();
+        store
+            .append(NewEvent::new("order-1", "OrderShipped", serde_json::json!({})).with_aggregate_type("Order"), None)
+            .await
+            .unwrap();
+
+        let events = store.get_events("order-1", 0, 10).await.unwrap();
+        assert_eq!(events[1].event_type, "OrderShipped");
+    }
+
+    #[tokio::test]
+    async fn category_reads_and_subscriptions_span_aggregates() {
+        let store = InMemoryEventStore::new();
+        let mut orders = store.subscribe_category("Order").await.unwrap();
+
+        store.append_event("order-1", placed("order-1"), None).await.unwrap();
+        store.append_event("order-2", placed("order-2"), None).await.unwrap();
+
+        assert_eq!(store.read_category("Order", 0, 10).await.unwrap().len(), 2);
+        assert!(store.read_category("Customer", 0, 10).await.unwrap().is_empty());
+        assert_eq!(orders.next().await.unwrap().aggregate_id, "order-1");
+        assert_eq!(orders.next().await.unwrap().aggregate_id, "order-2");
+    }
+
+    #[tokio::test]
+    async fn list_aggregates_reports_stream_metadata_in_pages() {
+        let store = InMemoryEventStore::new();
+        let first = store.append_event("order-1", placed("order-1"), None).await.unwrap();
+        store.append_event("order-2", placed("order-2"), None).await.unwrap();
+        let head = store.append_event("order-1", placed("order-1"), first.cid).await.unwrap();
+        store.append_event("quote-1", placed("quote-1"), None).await.unwrap();
+
+        let query = AggregateQuery::new().with_type("Order").with_prefix("order-").with_limit(1);
+        let page = store.list_aggregates(query.clone()).await.unwrap();
+        let info = &page.aggregates[0];
+
+        assert_eq!(info.aggregate_id, "order-1");
+        assert_eq!((info.first_sequence, info.last_sequence, info.event_count), (1, 3, 2));
+        assert_eq!(info.head_cid, head.cid.map(|cid| cid.to_string()));
+
+        let rest = store.list_aggregates(query.after(&page.next.unwrap())).await.unwrap();
+        assert_eq!(rest.aggregates[0].aggregate_id, "order-2");
+        assert!(rest.next.is_none());
+    }
+}
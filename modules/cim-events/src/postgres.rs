@@ -0,0 +1,714 @@
+//! Event store on PostgreSQL.
+//!
+//! Events are rows of one table, next to whatever read models the
+//! application keeps in the same database, so an append and a projection
+//! update can share a transaction. Positions come from a single counter row
+//! that each appending transaction locks and increments. Positions are
+//! therefore gap-free and committed in order, at the cost of serializing
+//! appends. A unique `(aggregate_id, version)` constraint backs expected
+//! version checks. Committed appends are announced with `NOTIFY`, which
+//! drives subscriptions in every process listening on the database.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::TimeZone;
+use cid::Cid;
+use futures::channel::mpsc;
+use futures::Stream;
+use sqlx::postgres::{PgListener, PgPool};
+use sqlx::PgConnection;
+
+use crate::catalog::{AggregateInfo, AggregateKey, AggregatePage, AggregateQuery};
+use crate::cid_policy::CidPolicy;
+use crate::domain::ExpectedVersion;
+use crate::event_store::{
+    check_append, ensure_same_type, EventMetadata, EventScope, EventStore, EventStoreError, NewEvent, ReadStream,
+    Result, StoredEvent, SubscribeFrom,
+};
+use crate::lifecycle::ensure_open;
+use crate::sql::{db_error, timestamp_ns, to_i64, validate_table_name};
+
+/// Events fetched per query while streaming an aggregate
+const PAGE_SIZE: i64 = 256;
+
+/// How often subscriptions poll if no notification arrives, to recover
+/// notifications lost while the listener reconnected
+const LISTEN_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A bound query parameter
+#[derive(Clone)]
+enum Arg {
+    Text(String),
+    Int(i64),
+}
+
+/// A `WHERE` clause with numbered parameters
+#[derive(Clone)]
+struct Filter {
+    sql: String,
+    args: Vec<Arg>,
+}
+
+impl Filter {
+    fn new() -> Self {
+        Self {
+            sql: "TRUE".to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Add a condition whose `?` stands for `arg`
+    fn and(mut self, condition: &str, arg: Arg) -> Self {
+        self.args.push(arg);
+        let condition = condition.replace('?', &format!("${}", self.args.len()));
+        self.sql = format!("{} AND {}", self.sql, condition);
+        self
+    }
+
+    fn scoped(self, scope: &EventScope) -> Self {
+        match scope {
+            EventScope::All => self,
+            EventScope::Aggregate(aggregate_id) => self.and("aggregate_id = ?", Arg::Text(aggregate_id.clone())),
+            EventScope::Category(aggregate_type) => self.and("aggregate_type = ?", Arg::Text(aggregate_type.clone())),
+        }
+    }
+}
+
+/// An aggregate's state at the start of an append, read with the position
+/// counter locked
+struct Head {
+    position: i64,
+    version: i64,
+    latest: Option<StoredEvent>,
+}
+
+/// Event store in a PostgreSQL database.
+///
+/// Message IDs are unique in the table, so they are remembered for as long
+/// as their events are kept.
+#[derive(Clone)]
+pub struct PostgresEventStore {
+    pool: PgPool,
+    table_name: String,
+    cid_policy: CidPolicy,
+}
+
+impl PostgresEventStore {
+    /// Connect to `database_url`, creating the events table if needed
+    pub async fn new(database_url: &str, table_name: &str) -> Result<Self> {
+        let pool = PgPool::connect(database_url).await.map_err(db_error)?;
+
+        Self::with_pool(pool, table_name).await
+    }
+
+    /// Use an existing pool, creating the events table if needed
+    pub async fn with_pool(pool: PgPool, table_name: &str) -> Result<Self> {
+        validate_table_name(table_name)?;
+
+        let statements = [
+            format!(
+                r#"
+                CREATE TABLE IF NOT EXISTS {t}_position (
+                    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+                    position BIGINT NOT NULL
+                )
+                "#,
+                t = table_name
+            ),
+            format!(
+                "INSERT INTO {t}_position (id, position) VALUES (TRUE, 0) ON CONFLICT (id) DO NOTHING",
+                t = table_name
+            ),
+            format!(
+                r#"
+                CREATE TABLE IF NOT EXISTS {t} (
+                    position BIGINT PRIMARY KEY,
+                    aggregate_id TEXT NOT NULL,
+                    aggregate_type TEXT NOT NULL,
+                    version BIGINT NOT NULL,
+                    event_type TEXT NOT NULL,
+                    message_id TEXT NOT NULL UNIQUE,
+                    correlation_id TEXT NOT NULL,
+                    causation_id TEXT,
+                    cid TEXT NOT NULL,
+                    parent_cid TEXT,
+                    timestamp_ns BIGINT NOT NULL,
+                    body JSON NOT NULL,
+                    UNIQUE (aggregate_id, version)
+                )
+                "#,
+                t = table_name
+            ),
+            format!("CREATE INDEX IF NOT EXISTS {t}_correlation ON {t} (correlation_id)", t = table_name),
+            format!("CREATE INDEX IF NOT EXISTS {t}_category ON {t} (aggregate_type, position)", t = table_name),
+            format!("CREATE INDEX IF NOT EXISTS {t}_timestamp ON {t} (timestamp_ns)", t = table_name),
+            format!("CREATE INDEX IF NOT EXISTS {t}_cid ON {t} (cid)", t = table_name),
+        ];
+        for statement in &statements {
+            sqlx::query(statement).execute(&pool).await.map_err(db_error)?;
+        }
+
+        Ok(Self {
+            pool,
+            table_name: table_name.to_string(),
+            cid_policy: CidPolicy::default(),
+        })
+    }
+
+    /// Hash function and codec used for new event CIDs
+    pub fn with_cid_policy(mut self, policy: CidPolicy) -> Self {
+        self.cid_policy = policy;
+        self
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Append events of one aggregate atomically, each linked to the one
+    /// before it and the first to the aggregate's current head.
+    ///
+    /// Fails with `ConcurrentModification` unless the aggregate is at the
+    /// expected version. Retrying a batch whose message IDs were all stored
+    /// returns the original receipts; a batch repeating only some of them is
+    /// rejected.
+    pub async fn append_batch(
+        &self,
+        aggregate_id: &str,
+        expected: ExpectedVersion,
+        events: Vec<NewEvent>,
+    ) -> Result<Vec<EventMetadata>> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        let receipts = self.append_batch_in(&mut tx, aggregate_id, expected, events).await?;
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(receipts)
+    }
+
+    /// [`append_batch`](Self::append_batch) inside a transaction the caller
+    /// commits, for example together with projection updates.
+    ///
+    /// Appends are serialized until that transaction ends, so keep it short.
+    /// Subscribers are notified when it commits.
+    pub async fn append_batch_in(
+        &self,
+        conn: &mut PgConnection,
+        aggregate_id: &str,
+        expected: ExpectedVersion,
+        events: Vec<NewEvent>,
+    ) -> Result<Vec<EventMetadata>> {
+        if let Some(event) = events.iter().find(|e| e.aggregate_id != aggregate_id) {
+            return Err(EventStoreError::InvalidConfig(format!(
+                "Batch for {} contains an event of {}",
+                aggregate_id, event.aggregate_id
+            )));
+        }
+
+        let head = self.lock_head(conn, aggregate_id).await?;
+        let message_ids: Vec<&str> = events.iter().map(|e| e.header.message_id.as_str()).collect();
+        let receipts = self.receipts(conn, &message_ids).await?;
+        if !events.is_empty() && receipts.len() == events.len() {
+            return Ok(receipts);
+        }
+        // Batches are stored whole, so a retry repeats all of its message IDs
+        // or none; a partial overlap is a different batch reusing them
+        if !receipts.is_empty() {
+            return Err(EventStoreError::InvalidConfig(format!(
+                "Batch for {} repeats the message IDs of {} stored events but not all of them",
+                aggregate_id,
+                receipts.len()
+            )));
+        }
+        if !expected.matches(head.version as u64) {
+            return Err(EventStoreError::ConcurrentModification);
+        }
+
+        let mut latest = head.latest.clone();
+        let mut stored = Vec::with_capacity(events.len());
+        for event in events {
+            ensure_open(latest.as_ref(), &event.event_type)?;
+
+            let mut stored_event = StoredEvent {
+                sequence: 0,
+                aggregate_id: event.aggregate_id,
+                aggregate_type: Some(event.aggregate_type),
+                event_type: event.event_type,
+                event_data: event.event_data,
+                payload_cid: None,
+                header: event.header,
+                cid: None,
+                parent_cid: latest.as_ref().and_then(|e| e.cid.clone()),
+                timestamp: chrono::Utc::now(),
+            };
+            ensure_same_type(latest.as_ref(), &stored_event)?;
+            let cid = self.cid_policy.cid(&stored_event.canonical_bytes()?);
+            stored_event.cid = Some(cid.to_string());
+
+            latest = Some(stored_event.clone());
+            stored.push(stored_event);
+        }
+
+        self.insert_rows(conn, &head, stored).await
+    }
+
+    /// Lock the position counter, then read the aggregate's head and version.
+    ///
+    /// Holding the lock until the transaction ends keeps positions gap-free
+    /// and committed in order, and makes the head read here current.
+    async fn lock_head(&self, conn: &mut PgConnection, aggregate_id: &str) -> Result<Head> {
+        let sql = format!("SELECT position FROM {}_position FOR UPDATE", self.table_name);
+        let (position,): (i64,) = sqlx::query_as(&sql).fetch_one(&mut *conn).await.map_err(db_error)?;
+
+        let latest = self
+            .select(
+                conn,
+                Filter::new().and("aggregate_id = ?", Arg::Text(aggregate_id.to_string())),
+                "ORDER BY position DESC LIMIT 1",
+            )
+            .await?
+            .pop();
+
+        let sql = format!("SELECT COALESCE(MAX(version), 0) FROM {} WHERE aggregate_id = $1", self.table_name);
+        let (version,): (i64,) = sqlx::query_as(&sql)
+            .bind(aggregate_id)
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(db_error)?;
+
+        Ok(Head {
+            position,
+            version,
+            latest,
+        })
+    }
+
+    /// Receipts of the stored events with these message IDs
+    async fn receipts(&self, conn: &mut PgConnection, message_ids: &[&str]) -> Result<Vec<EventMetadata>> {
+        let sql = format!(
+            "SELECT position, cid, timestamp_ns FROM {} WHERE message_id = ANY($1) ORDER BY position",
+            self.table_name
+        );
+        let rows: Vec<(i64, String, i64)> = sqlx::query_as(&sql)
+            .bind(message_ids)
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(db_error)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(position, cid, timestamp_ns)| EventMetadata {
+                sequence: position as u64,
+                cid: Cid::try_from(cid.as_str()).ok(),
+                timestamp: chrono::Utc.timestamp_nanos(timestamp_ns),
+                duplicate: true,
+            })
+            .collect())
+    }
+
+    /// Insert events after `head`, advance the counter and announce the new
+    /// position to listeners once the transaction commits
+    async fn insert_rows(&self, conn: &mut PgConnection, head: &Head, events: Vec<StoredEvent>) -> Result<Vec<EventMetadata>> {
+        let sql = format!(
+            r#"
+            INSERT INTO {} (position, aggregate_id, aggregate_type, version, event_type, message_id,
+                correlation_id, causation_id, cid, parent_cid, timestamp_ns, body)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12::json)
+            "#,
+            self.table_name
+        );
+
+        let mut receipts = Vec::with_capacity(events.len());
+        let mut position = head.position;
+        for (n, event) in events.iter().enumerate() {
+            position += 1;
+            let cid = event.verified_cid()?;
+            sqlx::query(&sql)
+                .bind(position)
+                .bind(&event.aggregate_id)
+                .bind(event.category())
+                .bind(head.version + 1 + n as i64)
+                .bind(&event.event_type)
+                .bind(&event.header.message_id)
+                .bind(&event.header.correlation_id)
+                .bind(&event.header.causation_id)
+                .bind(cid.to_string())
+                .bind(&event.parent_cid)
+                .bind(timestamp_ns(event.timestamp))
+                .bind(serde_json::to_string(event)?)
+                .execute(&mut *conn)
+                .await
+                .map_err(db_error)?;
+
+            receipts.push(EventMetadata {
+                sequence: position as u64,
+                cid: Some(cid),
+                timestamp: event.timestamp,
+                duplicate: false,
+            });
+        }
+
+        if position != head.position {
+            let sql = format!("UPDATE {}_position SET position = $1", self.table_name);
+            sqlx::query(&sql).bind(position).execute(&mut *conn).await.map_err(db_error)?;
+            sqlx::query("SELECT pg_notify($1, $2)")
+                .bind(&self.table_name)
+                .bind(position.to_string())
+                .execute(&mut *conn)
+                .await
+                .map_err(db_error)?;
+        }
+
+        Ok(receipts)
+    }
+
+    /// Append one prepared event whose parent CID must be the aggregate's head
+    async fn insert(&self, event: StoredEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+
+        let head = self.lock_head(&mut tx, &event.aggregate_id).await?;
+        if let Some(receipt) = self.receipts(&mut tx, &[event.header.message_id.as_str()]).await?.pop() {
+            return Ok(receipt);
+        }
+        check_append(head.latest.as_ref(), &event, parent_cid.as_ref())?;
+
+        let receipt = self.insert_rows(&mut tx, &head, vec![event]).await?.remove(0);
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(receipt)
+    }
+
+    /// Events matching `filter`, in the order and number `tail` gives
+    async fn select(&self, conn: &mut PgConnection, filter: Filter, tail: &str) -> Result<Vec<StoredEvent>> {
+        let sql = format!("SELECT position, body::text FROM {} WHERE {} {}", self.table_name, filter.sql, tail);
+        let mut query = sqlx::query_as::<_, (i64, String)>(&sql);
+        for arg in filter.args {
+            query = match arg {
+                Arg::Text(value) => query.bind(value),
+                Arg::Int(value) => query.bind(value),
+            };
+        }
+
+        let rows = query.fetch_all(&mut *conn).await.map_err(db_error)?;
+        rows.into_iter()
+            .map(|(position, body)| {
+                let mut event: StoredEvent = serde_json::from_str(&body)?;
+                event.sequence = position as u64;
+                Ok(event)
+            })
+            .collect()
+    }
+
+    async fn query(&self, filter: Filter, tail: &str) -> Result<Vec<StoredEvent>> {
+        let mut conn = self.pool.acquire().await.map_err(db_error)?;
+        self.select(&mut conn, filter, tail).await
+    }
+
+    /// Highest committed position
+    async fn position(&self) -> Result<i64> {
+        let sql = format!("SELECT position FROM {}_position", self.table_name);
+        let (position,): (i64,) = sqlx::query_as(&sql).fetch_one(&self.pool).await.map_err(db_error)?;
+        Ok(position)
+    }
+
+    /// Forward committed events after `position` to a subscriber until it
+    /// goes away
+    async fn follow(self, mut listener: PgListener, filter: Filter, mut position: i64, sender: mpsc::UnboundedSender<StoredEvent>) {
+        loop {
+            // A notification or the poll interval, whichever comes first;
+            // either way everything committed since is read by position
+            if let Ok(Err(e)) = tokio::time::timeout(LISTEN_POLL_INTERVAL, listener.recv()).await {
+                tracing::warn!("Event listener for {} failed: {}", self.table_name, e);
+                return;
+            }
+            if sender.is_closed() {
+                return;
+            }
+
+            let events = match self
+                .query(filter.clone().and("position > ?", Arg::Int(position)), "ORDER BY position")
+                .await
+            {
+                Ok(events) => events,
+                Err(e) => {
+                    tracing::warn!("Reading new events from {} failed: {}", self.table_name, e);
+                    continue;
+                }
+            };
+            for event in events {
+                position = event.sequence as i64;
+                if sender.unbounded_send(event).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventStore for PostgresEventStore {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        let mut stored_event = StoredEvent {
+            sequence: 0,
+            aggregate_id: event.aggregate_id,
+            aggregate_type: Some(event.aggregate_type),
+            event_type: event.event_type,
+            event_data: event.event_data,
+            payload_cid: None,
+            header: event.header,
+            cid: None,
+            parent_cid: parent_cid.map(|c| c.to_string()),
+            timestamp: chrono::Utc::now(),
+        };
+        let cid = self.cid_policy.cid(&stored_event.canonical_bytes()?);
+        stored_event.cid = Some(cid.to_string());
+
+        self.insert(stored_event, parent_cid).await
+    }
+
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        event.verified_cid()?;
+        let parent_cid = event.parent_cid_value()?;
+
+        self.insert(event, parent_cid).await
+    }
+
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        // Fetch a page at a time, resuming after the last position yielded
+        let store = self.clone();
+        let aggregate_id = aggregate_id.to_string();
+
+        let events = futures::stream::try_unfold(
+            (from_sequence, VecDeque::new()),
+            move |(from, mut page): (u64, VecDeque<StoredEvent>)| {
+                let store = store.clone();
+                let aggregate_id = aggregate_id.clone();
+                async move {
+                    if page.is_empty() {
+                        let filter = Filter::new()
+                            .and("aggregate_id = ?", Arg::Text(aggregate_id))
+                            .and("position >= ?", Arg::Int(to_i64(from)));
+                        page = store
+                            .query(filter, &format!("ORDER BY position LIMIT {}", PAGE_SIZE))
+                            .await?
+                            .into();
+                    }
+                    let Some(event) = page.pop_front() else {
+                        return Ok(None);
+                    };
+                    let next = event.sequence + 1;
+                    Ok::<_, EventStoreError>(Some((event, (next, page))))
+                }
+            },
+        );
+
+        Ok(Box::pin(events))
+    }
+
+    async fn get_events(
+        &self,
+        aggregate_id: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        let filter = Filter::new()
+            .and("aggregate_id = ?", Arg::Text(aggregate_id.to_string()))
+            .and("position >= ?", Arg::Int(to_i64(from_sequence)));
+        self.query(filter, &format!("ORDER BY position LIMIT {}", to_i64(limit as u64))).await
+    }
+
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        let filter = Filter::new()
+            .and("aggregate_id = ?", Arg::Text(aggregate_id.to_string()))
+            .and("position <= ?", Arg::Int(from_sequence.map_or(i64::MAX, to_i64)));
+        self.query(filter, &format!("ORDER BY position DESC LIMIT {}", to_i64(limit as u64))).await
+    }
+
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        let filter = Filter::new()
+            .scoped(&scope)
+            .and("timestamp_ns >= ?", Arg::Int(timestamp_ns(from)))
+            .and("timestamp_ns < ?", Arg::Int(timestamp_ns(to)));
+        let events = self.query(filter, "ORDER BY position").await?;
+        Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+    }
+
+    async fn get_events_by_correlation(
+        &self,
+        correlation_id: &str,
+    ) -> Result<Vec<StoredEvent>> {
+        let filter = Filter::new().and("correlation_id = ?", Arg::Text(correlation_id.to_string()));
+        self.query(filter, "ORDER BY position").await
+    }
+
+    async fn read_category(
+        &self,
+        aggregate_type: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        let filter = Filter::new()
+            .and("aggregate_type = ?", Arg::Text(aggregate_type.to_string()))
+            .and("position >= ?", Arg::Int(to_i64(from_sequence)));
+        self.query(filter, &format!("ORDER BY position LIMIT {}", to_i64(limit as u64))).await
+    }
+
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        let sql = format!("DELETE FROM {} WHERE aggregate_id = $1 AND position < $2", self.table_name);
+        let result = sqlx::query(&sql)
+            .bind(aggregate_id)
+            .bind(to_i64(before_sequence))
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        let sql = format!(
+            r#"
+            SELECT aggregate_type, aggregate_id, MIN(position), MAX(position), COUNT(*),
+                MIN(timestamp_ns), MAX(timestamp_ns)
+            FROM {}
+            GROUP BY aggregate_type, aggregate_id
+            "#,
+            self.table_name
+        );
+        let rows: Vec<(String, String, i64, i64, i64, i64, i64)> =
+            sqlx::query_as(&sql).fetch_all(&self.pool).await.map_err(db_error)?;
+
+        let keys = rows.iter().map(|row| AggregateKey::new(&row.0, &row.1)).collect();
+        let (keys, next) = query.page(keys)?;
+
+        let head_sql = format!("SELECT cid FROM {} WHERE position = $1", self.table_name);
+        let mut aggregates = Vec::with_capacity(keys.len());
+        for key in keys {
+            let Some(row) = rows.iter().find(|row| row.0 == key.aggregate_type && row.1 == key.aggregate_id) else {
+                continue;
+            };
+            let head_cid: Option<(String,)> = sqlx::query_as(&head_sql)
+                .bind(row.3)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(db_error)?;
+
+            aggregates.push(AggregateInfo {
+                aggregate_id: key.aggregate_id,
+                aggregate_type: key.aggregate_type,
+                first_sequence: row.2 as u64,
+                last_sequence: row.3 as u64,
+                event_count: row.4 as u64,
+                head_cid: head_cid.map(|(cid,)| cid),
+                created_at: chrono::Utc.timestamp_nanos(row.5),
+                updated_at: chrono::Utc.timestamp_nanos(row.6),
+            });
+        }
+
+        Ok(AggregatePage { aggregates, next })
+    }
+
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        // Listen before reading history, and read history only up to the
+        // committed position, so every later event is picked up by `follow`
+        let mut listener = PgListener::connect_with(&self.pool).await.map_err(db_error)?;
+        listener.listen(&self.table_name).await.map_err(db_error)?;
+        let position = self.position().await?;
+
+        let filter = Filter::new().scoped(&scope);
+        let upto = filter.clone().and("position <= ?", Arg::Int(position));
+        let history = match from {
+            SubscribeFrom::Beginning => self.query(upto, "ORDER BY position").await?,
+            SubscribeFrom::Sequence(sequence) => {
+                let upto = upto.and("position >= ?", Arg::Int(to_i64(sequence)));
+                self.query(upto, "ORDER BY position").await?
+            }
+            SubscribeFrom::Timestamp(timestamp) => {
+                let upto = upto.and("timestamp_ns >= ?", Arg::Int(timestamp_ns(timestamp)));
+                self.query(upto, "ORDER BY position").await?
+            }
+            SubscribeFrom::Last => self.query(upto, "ORDER BY position DESC LIMIT 1").await?,
+            SubscribeFrom::New => Vec::new(),
+        };
+        for event in history {
+            let _ = sender.unbounded_send(event);
+        }
+
+        tokio::spawn(self.clone().follow(listener, filter, position, sender));
+
+        Ok(Box::new(receiver))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn filters_number_their_parameters() {
+        let filter = Filter::new()
+            .scoped(&EventScope::Category("Order".to_string()))
+            .and("position >= ?", Arg::Int(3));
+
+        assert_eq!(filter.sql, "TRUE AND aggregate_type = $1 AND position >= $2");
+        assert_eq!(filter.args.len(), 2);
+        assert!(validate_table_name("events; DROP TABLE events").is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires PostgreSQL at DATABASE_URL"]
+    async fn batches_are_atomic_and_enforce_the_expected_version() {
+        let url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/cim_events".to_string());
+        let table = format!("events_{}", uuid::Uuid::new_v4().simple());
+        let store = PostgresEventStore::new(&url, &table).await.unwrap();
+        let batch = |n: usize| -> Vec<NewEvent> { (0..n).map(|i| NewEvent::new("order-1", "LineAdded", json!({ "line": i }))).collect() };
+
+        let first = store.append_batch("order-1", ExpectedVersion::NoStream, batch(2)).await.unwrap();
+        let stale = store.append_batch("order-1", ExpectedVersion::Exact(1), batch(3)).await;
+        let second = store.append_batch("order-1", ExpectedVersion::Exact(2), batch(1)).await.unwrap();
+
+        assert_eq!(first.iter().map(|r| r.sequence).collect::<Vec<_>>(), vec![1, 2]);
+        assert!(matches!(stale, Err(EventStoreError::ConcurrentModification)));
+        assert_eq!(second[0].sequence, 3);
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "requires PostgreSQL at DATABASE_URL"]
+    async fn batches_repeating_some_stored_message_ids_are_rejected() {
+        let url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/cim_events".to_string());
+        let table = format!("events_{}", uuid::Uuid::new_v4().simple());
+        let store = PostgresEventStore::new(&url, &table).await.unwrap();
+        let batch: Vec<NewEvent> = (0..2).map(|i| NewEvent::new("order-1", "LineAdded", json!({ "line": i }))).collect();
+
+        let first = store.append_batch("order-1", ExpectedVersion::NoStream, batch.clone()).await.unwrap();
+        let retry = store.append_batch("order-1", ExpectedVersion::Exact(2), batch.clone()).await.unwrap();
+        let mut extended = batch;
+        extended.push(NewEvent::new("order-1", "LineAdded", json!({ "line": 2 })));
+        let partial = store.append_batch("order-1", ExpectedVersion::Exact(2), extended).await;
+
+        let sequences = |receipts: &[EventMetadata]| receipts.iter().map(|r| r.sequence).collect::<Vec<_>>();
+        assert_eq!(sequences(&retry), sequences(&first));
+        assert!(retry.iter().all(|r| r.duplicate));
+        assert!(matches!(partial, Err(EventStoreError::InvalidConfig(_))));
+        assert_eq!(store.get_events("order-1", 0, 10).await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,349 @@
+//! Time-travel rehydration of event-sourced aggregates.
+//!
+//! Aggregates are rebuilt by replaying their events through
+//! [`EventSourced::apply`], stopping at a version or a point in time, or one
+//! event at a time for debugging. A truncated aggregate is replayed from the
+//! snapshot in the checkpoint that describes its purged prefix.
+
+use std::fmt::Display;
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt, TryStreamExt};
+use serde::de::DeserializeOwned;
+
+use crate::domain::EventSourced;
+use crate::event_store::{EventStore, EventStoreError, ReadStream, Result, StoredEvent};
+use crate::lifecycle::{is_lifecycle_event, Checkpoint};
+
+/// Point in an aggregate's history to rehydrate to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// State once the aggregate reached this version
+    Version(u64),
+
+    /// State including every event stored at or before this time
+    Timestamp(chrono::DateTime<chrono::Utc>),
+}
+
+impl AsOf {
+    /// Whether applying `event` to `state` would go past this point
+    fn reached<A: EventSourced>(&self, state: &A, event: &StoredEvent) -> bool {
+        match self {
+            AsOf::Version(version) => state.version() >= *version,
+            AsOf::Timestamp(timestamp) => event.timestamp > *timestamp,
+        }
+    }
+
+    /// Whether this point may lie in the purged prefix of an aggregate
+    /// restored to `state`, given the first event left to apply or, if there
+    /// is none, the checkpoint
+    fn precedes<A: EventSourced>(&self, state: &A, next: &StoredEvent) -> bool {
+        match self {
+            AsOf::Version(version) => state.version() > *version,
+            AsOf::Timestamp(timestamp) => next.timestamp > *timestamp,
+        }
+    }
+}
+
+/// Aggregate state after one replayed event
+#[derive(Debug, Clone)]
+pub struct ReplayStep<A> {
+    /// The event just applied
+    pub event: StoredEvent,
+
+    /// State after applying it
+    pub state: A,
+}
+
+/// Stream of replay steps, one per event
+pub type ReplaySteps<'a, A> = Pin<Box<dyn Stream<Item = Result<ReplayStep<A>>> + Send + 'a>>;
+
+/// Rebuild an aggregate as it was at a version or point in time.
+///
+/// `initial` is the empty aggregate; only events up to `as_of` are applied.
+/// A point before the oldest event kept by a truncation cannot be rebuilt
+/// and is an error.
+pub async fn load_as_of<S, A>(store: &S, aggregate_id: &str, initial: A, as_of: AsOf) -> Result<A>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + DeserializeOwned,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let Restored {
+        mut state,
+        mut checkpoint,
+        mut events,
+    } = restore(store, aggregate_id, initial).await?;
+
+    while let Some(event) = events.try_next().await? {
+        if is_lifecycle_event(&event) {
+            continue;
+        }
+        // Only the state the aggregate was restored to needs checking
+        if checkpoint.take().is_some() {
+            ensure_kept(aggregate_id, &as_of, &state, &event)?;
+        }
+        if as_of.reached(&state, &event) {
+            break;
+        }
+        apply_event(store, &mut state, &event).await?;
+    }
+
+    if let Some(checkpoint) = checkpoint {
+        ensure_kept(aggregate_id, &as_of, &state, &checkpoint)?;
+    }
+
+    Ok(state)
+}
+
+/// Replay an aggregate one event at a time, yielding the state after each.
+///
+/// Tombstones and checkpoints are skipped, as they are not the aggregate's own
+/// events. A truncated aggregate starts from its checkpoint's snapshot.
+pub async fn replay_steps<'a, S, A>(store: &'a S, aggregate_id: &str, initial: A) -> Result<ReplaySteps<'a, A>>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + DeserializeOwned + Clone + Send + 'a,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let restored = restore(store, aggregate_id, initial).await?;
+    Ok(replay_restored(store, restored))
+}
+
+/// Replay the events left to apply to a restored aggregate, yielding the
+/// state after each
+pub(crate) fn replay_restored<'a, S, A>(store: &'a S, restored: Restored<A>) -> ReplaySteps<'a, A>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + Clone + Send + 'a,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let Restored { state: initial, events, .. } = restored;
+
+    let steps = futures::stream::try_unfold((events, initial), move |(mut events, mut state)| async move {
+        let event = loop {
+            match events.try_next().await? {
+                Some(event) if is_lifecycle_event(&event) => continue,
+                Some(event) => break event,
+                None => return Ok(None),
+            }
+        };
+        apply_event(store, &mut state, &event).await?;
+
+        let step = ReplayStep {
+            event,
+            state: state.clone(),
+        };
+        Ok(Some((step, (events, state))))
+    });
+
+    Box::pin(steps)
+}
+
+/// An aggregate restored from the checkpoint of its last truncation, if it
+/// was truncated, with the events still to apply to it
+pub(crate) struct Restored<A> {
+    pub(crate) state: A,
+
+    /// The checkpoint event the state was restored from
+    pub(crate) checkpoint: Option<StoredEvent>,
+
+    pub(crate) events: ReadStream,
+}
+
+/// State to replay a possibly truncated aggregate onto, and the events still
+/// to apply to it
+pub(crate) async fn restore<S, A>(store: &S, aggregate_id: &str, initial: A) -> Result<Restored<A>>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + DeserializeOwned,
+{
+    let mut events = store.read_stream(aggregate_id, 0).await?;
+    let Some(first) = events.try_next().await? else {
+        return Ok(Restored {
+            state: initial,
+            checkpoint: None,
+            events,
+        });
+    };
+
+    // An intact stream starts at the root of its chain
+    let Some(prefix_head) = first.parent_cid.clone() else {
+        return Ok(Restored {
+            state: initial,
+            checkpoint: None,
+            events: Box::pin(futures::stream::iter([Ok(first)]).chain(events)),
+        });
+    };
+
+    // The checkpoint describing the purged prefix follows the events kept,
+    // or is the only event left
+    let mut kept = Vec::new();
+    let mut next = Some(first);
+    while let Some(event) = next {
+        if let Some(checkpoint) = Checkpoint::from_event(&event).filter(|c| c.prefix_head_cid == prefix_head) {
+            let state = match checkpoint.snapshot {
+                Some(snapshot) => {
+                    let mut state: A = serde_json::from_value(snapshot.state)?;
+                    while state.version() < snapshot.version {
+                        state.increment_version();
+                    }
+                    state
+                }
+                None => initial,
+            };
+            kept.push(Ok(event.clone()));
+            return Ok(Restored {
+                state,
+                checkpoint: Some(event),
+                events: Box::pin(futures::stream::iter(kept).chain(events)),
+            });
+        }
+
+        kept.push(Ok(event));
+        next = events.try_next().await?;
+    }
+
+    Err(EventStoreError::Rehydration(format!(
+        "Aggregate {} starts after a purge no checkpoint describes",
+        aggregate_id
+    )))
+}
+
+/// Reject rebuilding a truncated aggregate at a point its purged prefix
+/// would be needed for
+fn ensure_kept<A: EventSourced>(aggregate_id: &str, as_of: &AsOf, state: &A, next: &StoredEvent) -> Result<()> {
+    if as_of.precedes(state, next) {
+        return Err(EventStoreError::Rehydration(format!(
+            "Aggregate {} was truncated; {:?} predates the events kept",
+            aggregate_id, as_of
+        )));
+    }
+
+    Ok(())
+}
+
+/// Deserialize a stored event and apply it to the aggregate
+pub(crate) async fn apply_event<S, A>(store: &S, state: &mut A, event: &StoredEvent) -> Result<()>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let data = store.load_event_data(event).await?;
+    let typed: A::Event = serde_json::from_value(data)?;
+
+    state.apply(&typed).map_err(|e| {
+        EventStoreError::Rehydration(format!(
+            "Applying {} at sequence {} failed: {}",
+            event.event_type, event.sequence, e
+        ))
+    })?;
+    state.increment_version();
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Event;
+    use crate::event_store::EventStoreExt;
+    use crate::memory::InMemoryEventStore;
+    use futures::StreamExt;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct Deposited {
+        account_id: String,
+        amount: i64,
+    }
+
+    impl Event for Deposited {
+        fn event_type(&self) -> &str {
+            "Deposited"
+        }
+
+        fn aggregate_id(&self) -> &str {
+            &self.account_id
+        }
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    struct Account {
+        balance: i64,
+        version: u64,
+    }
+
+    impl EventSourced for Account {
+        type Event = Deposited;
+        type Error = String;
+
+        fn apply(&mut self, event: &Deposited) -> std::result::Result<(), String> {
+            self.balance += event.amount;
+            Ok(())
+        }
+
+        fn aggregate_id(&self) -> &str {
+            "acc-1"
+        }
+
+        fn version(&self) -> u64 {
+            self.version
+        }
+
+        fn increment_version(&mut self) {
+            self.version += 1;
+        }
+    }
+
+    async fn store_with_deposits(amounts: &[i64]) -> InMemoryEventStore {
+        let store = InMemoryEventStore::new();
+        for amount in amounts {
+            let event = Deposited {
+                account_id: "acc-1".to_string(),
+                amount: *amount,
+            };
+            store.append_event("acc-1", event, None).await.unwrap();
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn load_as_of_version_applies_only_earlier_events() {
+        let store = store_with_deposits(&[10, 20, 30]).await;
+
+        let account = load_as_of(&store, "acc-1", Account::default(), AsOf::Version(2)).await.unwrap();
+
+        assert_eq!(account.balance, 30);
+        assert_eq!(account.version, 2);
+    }
+
+    #[tokio::test]
+    async fn load_as_of_timestamp_includes_events_at_that_time() {
+        let store = store_with_deposits(&[10, 20, 30]).await;
+        let second = store.get_events("acc-1", 0, 10).await.unwrap()[1].timestamp;
+
+        let account = load_as_of(&store, "acc-1", Account::default(), AsOf::Timestamp(second)).await.unwrap();
+
+        assert_eq!(account.balance, 30);
+    }
+
+    #[tokio::test]
+    async fn replay_steps_yields_state_after_each_event() {
+        let store = store_with_deposits(&[10, 20, 30]).await;
+
+        let balances: Vec<i64> = replay_steps(&store, "acc-1", Account::default())
+            .await
+            .unwrap()
+            .map(|step| step.unwrap().state.balance)
+            .collect()
+            .await;
+
+        assert_eq!(balances, vec![10, 30, 60]);
+    }
+}
@@ -0,0 +1,415 @@
+//! Retries with backoff and circuit breaking around any event store.
+//!
+//! Transient failures (timeouts, lost connections, JetStream leader
+//! elections) are retried with exponential backoff and jitter. Appends are
+//! safe to retry: a retried event keeps its message ID, which the store
+//! deduplicates on. After repeated failures the circuit opens and calls fail
+//! fast with [`EventStoreError::Unavailable`] until a trial call succeeds.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use cid::Cid;
+use futures::Stream;
+use rand::Rng;
+
+use crate::catalog::{AggregatePage, AggregateQuery};
+use crate::event_store::{
+    EventMetadata, EventScope, EventStore, EventStoreError, NewEvent, ReadStream, Result, StoredEvent,
+    SubscribeFrom,
+};
+
+/// How failed calls are retried
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts per call, the first one included
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+
+    /// Pick each delay uniformly between zero and the backoff, so clients
+    /// that failed together do not retry together
+    pub jitter: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: true,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before the given retry, counting from 1
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+
+        if self.jitter {
+            Duration::from_secs_f64(rand::thread_rng().gen_range(0.0..=backoff))
+        } else {
+            Duration::from_secs_f64(backoff)
+        }
+    }
+}
+
+/// State of a circuit breaker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls go through
+    Closed,
+
+    /// Calls fail fast until the reset timeout has passed
+    Open,
+
+    /// The reset timeout has passed; the next call is a trial
+    HalfOpen,
+}
+
+/// Opens after consecutive transient failures and fails calls fast while open.
+///
+/// Clones share their state.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    state: Arc<Mutex<BreakerState>>,
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    trial_in_flight: bool,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30))
+    }
+}
+
+impl CircuitBreaker {
+    /// Open after `failure_threshold` consecutive failures, and let a trial
+    /// call through `reset_timeout` after opening
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+            state: Arc::new(Mutex::new(BreakerState::default())),
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.opened_at {
+            None => CircuitState::Closed,
+            Some(opened_at) if opened_at.elapsed() < self.reset_timeout => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Admit a call, or fail fast while the circuit is open
+    fn admit(&self) -> Result<Admission<'_>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        match state.opened_at {
+            None => Ok(Admission { breaker: self, trial: false }),
+            Some(opened_at) if opened_at.elapsed() < self.reset_timeout => Err(EventStoreError::Unavailable(format!(
+                "Circuit open after {} consecutive failures",
+                state.consecutive_failures
+            ))),
+            Some(_) if state.trial_in_flight => Err(EventStoreError::Unavailable(
+                "Circuit half-open, waiting on a trial call".to_string(),
+            )),
+            Some(_) => {
+                state.trial_in_flight = true;
+                Ok(Admission { breaker: self, trial: true })
+            }
+        }
+    }
+
+    fn record_success(&self) {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner()) = BreakerState::default();
+    }
+
+    fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.consecutive_failures += 1;
+        state.trial_in_flight = false;
+        if state.consecutive_failures >= self.failure_threshold {
+            state.opened_at = Some(Instant::now());
+        }
+    }
+}
+
+/// A call let through by a circuit breaker.
+///
+/// A trial dropped before its outcome is recorded, for example because the
+/// caller cancelled it, counts as a failure, so the breaker never waits on it
+/// forever.
+struct Admission<'a> {
+    breaker: &'a CircuitBreaker,
+    trial: bool,
+}
+
+impl Admission<'_> {
+    fn record_success(mut self) {
+        self.trial = false;
+        self.breaker.record_success();
+    }
+
+    fn record_failure(&mut self) {
+        self.trial = false;
+        self.breaker.record_failure();
+    }
+}
+
+impl Drop for Admission<'_> {
+    fn drop(&mut self) {
+        if self.trial {
+            self.breaker.record_failure();
+        }
+    }
+}
+
+/// Event store wrapper that retries transient failures and breaks the
+/// circuit while the backend is unreachable.
+///
+/// Only opening a read or subscription is retried; errors while consuming
+/// the returned stream are passed through.
+#[derive(Debug, Clone)]
+pub struct ResilientEventStore<S> {
+    inner: S,
+    retry: RetryPolicy,
+    breaker: CircuitBreaker,
+}
+
+impl<S: EventStore> ResilientEventStore<S> {
+    /// Wrap a store with the default retry policy and circuit breaker
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            retry: RetryPolicy::default(),
+            breaker: CircuitBreaker::default(),
+        }
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Health of the backend as seen by the circuit breaker
+    pub fn health(&self) -> CircuitState {
+        self.breaker.state()
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    async fn run<T, F, Fut>(&self, mut call: F) -> Result<T>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T>> + Send,
+        T: Send,
+    {
+        let mut admission = self.breaker.admit()?;
+
+        let mut attempt = 1;
+        loop {
+            match call().await {
+                Err(e) if e.is_transient() => {
+                    admission.record_failure();
+                    if attempt >= self.retry.max_attempts || self.breaker.state() != CircuitState::Closed {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(self.retry.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                // Any other outcome means the backend answered
+                result => {
+                    admission.record_success();
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<S: EventStore> EventStore for ResilientEventStore<S> {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        self.run(|| self.inner.append(event.clone(), parent_cid)).await
+    }
+
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        self.run(|| self.inner.append_stored_event(event.clone())).await
+    }
+
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        self.run(|| self.inner.read_stream(aggregate_id, from_sequence)).await
+    }
+
+    async fn get_events(&self, aggregate_id: &str, from_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.run(|| self.inner.get_events(aggregate_id, from_sequence, limit)).await
+    }
+
+    async fn get_events_by_correlation(&self, correlation_id: &str) -> Result<Vec<StoredEvent>> {
+        self.run(|| self.inner.get_events_by_correlation(correlation_id)).await
+    }
+
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.run(|| self.inner.read_backward(aggregate_id, from_sequence, limit)).await
+    }
+
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        self.run(|| self.inner.read_between(scope.clone(), from, to)).await
+    }
+
+    async fn read_category(&self, aggregate_type: &str, from_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.run(|| self.inner.read_category(aggregate_type, from_sequence, limit)).await
+    }
+
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        self.run(|| self.inner.purge(aggregate_id, before_sequence)).await
+    }
+
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        self.run(|| self.inner.list_aggregates(query.clone())).await
+    }
+
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        self.run(|| self.inner.subscribe_with(scope.clone(), from)).await
+    }
+
+    async fn validate_cid_chain(&self, aggregate_id: &str) -> Result<bool> {
+        self.run(|| self.inner.validate_cid_chain(aggregate_id)).await
+    }
+
+    async fn load_event_data(&self, event: &StoredEvent) -> Result<serde_json::Value> {
+        self.run(|| self.inner.load_event_data(event)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{transient, FaultyStore};
+    use async_nats::jetstream::context;
+
+    fn fast_retries(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn backoff_grows_to_its_cap() {
+        let policy = RetryPolicy {
+            jitter: false,
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(50));
+        assert_eq!(policy.backoff(3), Duration::from_millis(200));
+        assert_eq!(policy.backoff(30), Duration::from_secs(5));
+        assert!(RetryPolicy::default().backoff(3) <= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn only_unanswered_requests_are_transient() {
+        let publish = |kind| EventStoreError::from(context::PublishError::from(kind));
+
+        assert!(transient().is_transient());
+        assert!(publish(context::PublishErrorKind::BrokenPipe).is_transient());
+        assert!(!publish(context::PublishErrorKind::WrongLastSequence).is_transient());
+        assert!(!EventStoreError::Nats("stream not found".into()).is_transient());
+        assert!(!EventStoreError::ConcurrentModification.is_transient());
+    }
+
+    #[tokio::test]
+    async fn transient_append_failures_are_retried_once_stored() {
+        let store = ResilientEventStore::new(FaultyStore::failing(2)).with_retry_policy(fast_retries(3));
+
+        let receipt = store.append(NewEvent::new("order-1", "OrderPlaced", serde_json::json!({})), None).await;
+
+        assert_eq!(receipt.unwrap().sequence, 1);
+        assert_eq!(store.get_events("order-1", 0, 10).await.unwrap().len(), 1);
+        assert_eq!(store.health(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn open_circuits_fail_fast_until_a_trial_succeeds() {
+        let store = ResilientEventStore::new(FaultyStore::failing(2))
+            .with_retry_policy(fast_retries(1))
+            .with_circuit_breaker(CircuitBreaker::new(2, Duration::from_millis(20)));
+        let event = || NewEvent::new("order-1", "OrderPlaced", serde_json::json!({}));
+
+        assert!(store.append(event(), None).await.is_err());
+        assert!(store.append(event(), None).await.is_err());
+        assert_eq!(store.health(), CircuitState::Open);
+        assert!(matches!(store.append(event(), None).await, Err(EventStoreError::Unavailable(_))));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(store.health(), CircuitState::HalfOpen);
+        assert!(store.append(event(), None).await.is_ok());
+        assert_eq!(store.health(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_trial_counts_as_a_failure() {
+        let store = ResilientEventStore::new(FaultyStore::failing(1))
+            .with_retry_policy(fast_retries(1))
+            .with_circuit_breaker(CircuitBreaker::new(1, Duration::from_millis(20)));
+        let event = || NewEvent::new("order-1", "OrderPlaced", serde_json::json!({}));
+        assert!(store.append(event(), None).await.is_err());
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let trial = store.run(futures::future::pending::<Result<()>>);
+        assert!(tokio::time::timeout(Duration::from_millis(10), trial).await.is_err());
+        assert_eq!(store.health(), CircuitState::Open);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(store.append(event(), None).await.is_ok());
+        assert_eq!(store.health(), CircuitState::Closed);
+    }
+}
@@ -0,0 +1,347 @@
+//! Per event type and per category retention with snapshot compaction.
+//!
+//! Instead of letting the stream's `max_age` drop events, expired events are
+//! compacted away: the aggregate is replayed up to its first event that must
+//! be kept, its state is captured in a checkpoint, and only then is the
+//! expired prefix purged. Compacted aggregates stay loadable through
+//! [`load_compacted`] and their chains stay verifiable.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::catalog::AggregateQuery;
+use crate::domain::EventSourced;
+use crate::event_store::{EventStore, Result, StoredEvent};
+use crate::lifecycle::{head, is_lifecycle_event, truncate_at, Snapshot, Truncation};
+use crate::rehydrate::{apply_event, restore, Restored};
+
+/// How long events are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retain {
+    Forever,
+    For(Duration),
+}
+
+/// Retention rules, by event type and by aggregate category.
+///
+/// A rule for an event type takes precedence over one for the event's
+/// category, which takes precedence over the default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionRules {
+    default: Retain,
+    event_types: HashMap<String, Retain>,
+    categories: HashMap<String, Retain>,
+}
+
+impl Default for RetentionRules {
+    fn default() -> Self {
+        Self {
+            default: Retain::Forever,
+            event_types: HashMap::new(),
+            categories: HashMap::new(),
+        }
+    }
+}
+
+impl RetentionRules {
+    /// Keep everything forever until rules say otherwise
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retention of events no other rule matches
+    pub fn with_default(mut self, retain: Retain) -> Self {
+        self.default = retain;
+        self
+    }
+
+    pub fn for_event_type(mut self, event_type: &str, retain: Retain) -> Self {
+        self.event_types.insert(event_type.to_string(), retain);
+        self
+    }
+
+    pub fn for_category(mut self, aggregate_type: &str, retain: Retain) -> Self {
+        self.categories.insert(aggregate_type.to_string(), retain);
+        self
+    }
+
+    /// Retention that applies to an event
+    pub fn retention_of(&self, event: &StoredEvent) -> Retain {
+        self.event_types
+            .get(&event.event_type)
+            .or_else(|| self.categories.get(event.category()))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Whether an event has outlived its retention at `now`
+    pub fn is_expired(&self, event: &StoredEvent, now: chrono::DateTime<chrono::Utc>) -> bool {
+        match self.retention_of(event) {
+            Retain::Forever => false,
+            Retain::For(max_age) => chrono::Duration::from_std(max_age)
+                .map(|max_age| event.timestamp + max_age <= now)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Rebuild an aggregate that may have been compacted.
+///
+/// Replay starts from the snapshot in the checkpoint that describes the
+/// purged prefix, or from `initial` if the stream was never truncated.
+pub async fn load_compacted<S, A>(store: &S, aggregate_id: &str, initial: A) -> Result<A>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + DeserializeOwned,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let Restored { mut state, mut events, .. } = restore(store, aggregate_id, initial).await?;
+
+    while let Some(event) = events.try_next().await? {
+        if !is_lifecycle_event(&event) {
+            apply_event(store, &mut state, &event).await?;
+        }
+    }
+
+    Ok(state)
+}
+
+/// Compact an aggregate's expired events into a snapshot checkpoint.
+///
+/// Only a prefix can be removed, so compaction stops at the first event that
+/// must be kept. Returns `None` when nothing has expired.
+pub async fn compact<S, A>(store: &S, aggregate_id: &str, initial: A, rules: &RetentionRules) -> Result<Option<Truncation>>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + Serialize + DeserializeOwned,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let now = chrono::Utc::now();
+    let head = head(store, aggregate_id).await?;
+    let Restored { mut state, mut events, .. } = restore(store, aggregate_id, initial).await?;
+
+    // Earlier checkpoints are superseded by the new snapshot, so they expire
+    // along with the events around them
+    let mut expired = 0;
+    let mut first_kept = head.sequence + 1;
+    while let Some(event) = events.try_next().await? {
+        if event.sequence > head.sequence {
+            break;
+        }
+        if is_lifecycle_event(&event) {
+            continue;
+        }
+        if !rules.is_expired(&event, now) {
+            first_kept = event.sequence;
+            break;
+        }
+        apply_event(store, &mut state, &event).await?;
+        expired += 1;
+    }
+
+    if expired == 0 {
+        return Ok(None);
+    }
+
+    let snapshot = Snapshot {
+        version: state.version(),
+        state: serde_json::to_value(&state)?,
+    };
+    truncate_at(store, head, first_kept, None, Some(snapshot)).await.map(Some)
+}
+
+/// Compact every aggregate of one type
+pub async fn compact_category<S, A>(
+    store: &S,
+    aggregate_type: &str,
+    initial: A,
+    rules: &RetentionRules,
+) -> Result<Vec<Truncation>>
+where
+    S: EventStore + ?Sized,
+    A: EventSourced + Serialize + DeserializeOwned + Clone,
+    A::Event: DeserializeOwned,
+    A::Error: Display,
+{
+    let mut query = AggregateQuery::new().with_type(aggregate_type);
+    let mut truncations = Vec::new();
+
+    loop {
+        let page = store.list_aggregates(query.clone()).await?;
+        for info in &page.aggregates {
+            if let Some(truncation) = compact(store, &info.aggregate_id, initial.clone(), rules).await? {
+                truncations.push(truncation);
+            }
+        }
+
+        match page.next {
+            Some(cursor) => query = query.after(&cursor),
+            None => return Ok(truncations),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::Event;
+    use crate::event_store::{EventStoreError, EventStoreExt};
+    use crate::memory::InMemoryEventStore;
+    use crate::rehydrate::{load_as_of, replay_steps, AsOf};
+    use futures::StreamExt;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    enum AccountEvent {
+        Deposited { amount: i64 },
+        Noted { text: String },
+    }
+
+    impl Event for AccountEvent {
+        fn event_type(&self) -> &str {
+            match self {
+                AccountEvent::Deposited { .. } => "Deposited",
+                AccountEvent::Noted { .. } => "Noted",
+            }
+        }
+
+        fn aggregate_id(&self) -> &str {
+            "acc-1"
+        }
+
+        fn aggregate_type(&self) -> &str {
+            "Account"
+        }
+    }
+
+    #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+    struct Account {
+        balance: i64,
+        notes: Vec<String>,
+        #[serde(skip)]
+        version: u64,
+    }
+
+    impl EventSourced for Account {
+        type Event = AccountEvent;
+        type Error = String;
+
+        fn apply(&mut self, event: &AccountEvent) -> std::result::Result<(), String> {
+            match event {
+                AccountEvent::Deposited { amount } => self.balance += amount,
+                AccountEvent::Noted { text } => self.notes.push(text.clone()),
+            }
+            Ok(())
+        }
+
+        fn aggregate_id(&self) -> &str {
+            "acc-1"
+        }
+
+        fn version(&self) -> u64 {
+            self.version
+        }
+
+        fn increment_version(&mut self) {
+            self.version += 1;
+        }
+    }
+
+    async fn store_with_history() -> InMemoryEventStore {
+        let store = InMemoryEventStore::new();
+        let events = [
+            AccountEvent::Deposited { amount: 10 },
+            AccountEvent::Deposited { amount: 20 },
+            AccountEvent::Noted { text: "vip".to_string() },
+            AccountEvent::Deposited { amount: 30 },
+        ];
+        let mut parent = None;
+        for event in events {
+            parent = store.append_event("acc-1", event, parent).await.unwrap().cid;
+        }
+        store
+    }
+
+    #[test]
+    fn event_type_rules_override_category_rules() {
+        let rules = RetentionRules::new()
+            .for_category("Account", Retain::For(Duration::ZERO))
+            .for_event_type("Noted", Retain::Forever);
+        let mut event = StoredEvent {
+            sequence: 1,
+            aggregate_id: "acc-1".to_string(),
+            aggregate_type: Some("Account".to_string()),
+            event_type: "Deposited".to_string(),
+            event_data: serde_json::Value::Null,
+            payload_cid: None,
+            header: crate::domain::EventHeader::new(),
+            cid: None,
+            parent_cid: None,
+            timestamp: chrono::Utc::now(),
+        };
+
+        assert!(rules.is_expired(&event, chrono::Utc::now()));
+        event.event_type = "Noted".to_string();
+        assert!(!rules.is_expired(&event, chrono::Utc::now()));
+    }
+
+    #[tokio::test]
+    async fn compaction_keeps_aggregates_loadable_and_verifiable() {
+        let store = store_with_history().await;
+        let rules = RetentionRules::new().for_event_type("Deposited", Retain::For(Duration::ZERO));
+
+        let truncation = compact(&store, "acc-1", Account::default(), &rules).await.unwrap().unwrap();
+        let account: Account = load_compacted(&store, "acc-1", Account::default()).await.unwrap();
+
+        assert_eq!(truncation.removed, 2);
+        assert_eq!(account.balance, 60);
+        assert_eq!(account.notes, vec!["vip".to_string()]);
+        assert_eq!(account.version, 4);
+        assert!(store.validate_cid_chain("acc-1").await.unwrap());
+
+        // The kept note blocks any further compaction
+        assert!(compact(&store, "acc-1", Account::default(), &rules).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn time_travel_starts_from_the_compaction_snapshot() {
+        let store = store_with_history().await;
+        let rules = RetentionRules::new().for_event_type("Deposited", Retain::For(Duration::ZERO));
+        compact(&store, "acc-1", Account::default(), &rules).await.unwrap();
+
+        let account = load_as_of(&store, "acc-1", Account::default(), AsOf::Version(3)).await.unwrap();
+        let balances: Vec<i64> = replay_steps(&store, "acc-1", Account::default())
+            .await
+            .unwrap()
+            .map(|step| step.unwrap().state.balance)
+            .collect()
+            .await;
+
+        assert_eq!((account.balance, account.notes.len()), (30, 1));
+        assert_eq!(balances, vec![30, 60]);
+        assert!(matches!(
+            load_as_of(&store, "acc-1", Account::default(), AsOf::Version(1)).await,
+            Err(EventStoreError::Rehydration(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn compaction_of_a_fully_expired_history_leaves_a_snapshot() {
+        let store = store_with_history().await;
+        let rules = RetentionRules::new().with_default(Retain::For(Duration::ZERO));
+
+        compact_category(&store, "Account", Account::default(), &rules).await.unwrap();
+        let account: Account = load_compacted(&store, "acc-1", Account::default()).await.unwrap();
+
+        assert_eq!(store.get_events("acc-1", 0, 10).await.unwrap().len(), 1);
+        assert_eq!((account.balance, account.version), (60, 4));
+        assert!(store.validate_cid_chain("acc-1").await.unwrap());
+    }
+}
@@ -0,0 +1,34 @@
+//! Helpers shared by the SQL backends.
+
+use crate::event_store::{EventStoreError, Result};
+
+/// Table names are interpolated into SQL, and used as a notification channel
+/// by Postgres, so only plain identifiers are allowed
+pub(crate) fn validate_table_name(table_name: &str) -> Result<()> {
+    let mut chars = table_name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(EventStoreError::InvalidConfig(format!("Invalid table name: {:?}", table_name)));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn timestamp_ns(timestamp: chrono::DateTime<chrono::Utc>) -> i64 {
+    timestamp.timestamp_nanos_opt().unwrap_or(i64::MAX)
+}
+
+/// SQL integers are signed; larger sequences and limits are saturated
+pub(crate) fn to_i64(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Map driver errors, reporting a lost race on an aggregate's next version
+/// as a concurrent modification
+pub(crate) fn db_error(e: sqlx::Error) -> EventStoreError {
+    match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => EventStoreError::ConcurrentModification,
+        _ => EventStoreError::Database(e.to_string()),
+    }
+}
@@ -0,0 +1,518 @@
+//! Single-file event store on SQLite.
+//!
+//! Events live in one table that can be inspected with plain SQL. The
+//! `sequence` primary key orders events across all aggregates and is never
+//! reused, and a unique `(aggregate_id, version)` constraint rejects an
+//! append that raced another writer to the same aggregate. Correlation IDs,
+//! categories, timestamps and CIDs are indexed columns next to the full
+//! event. Subscriptions are fed in-process by the store that committed the
+//! event, so they only see appends made through the same `SqliteEventStore`
+//! (or its clones).
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::TimeZone;
+use cid::Cid;
+use futures::channel::mpsc;
+use futures::Stream;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool};
+use sqlx::SqliteConnection;
+use tokio::sync::Mutex;
+
+use crate::catalog::{AggregateInfo, AggregateKey, AggregatePage, AggregateQuery};
+use crate::cid_policy::CidPolicy;
+use crate::event_store::{
+    check_append, EventMetadata, EventScope, EventStore, EventStoreError, NewEvent, ReadStream, Result,
+    StoredEvent, SubscribeFrom,
+};
+use crate::sql::{db_error, timestamp_ns, to_i64, validate_table_name};
+
+/// Events fetched per query while streaming an aggregate
+const PAGE_SIZE: i64 = 256;
+
+struct Subscriber {
+    scope: EventScope,
+    sender: mpsc::UnboundedSender<StoredEvent>,
+}
+
+/// A bound query parameter
+enum Arg {
+    Text(String),
+    Int(i64),
+}
+
+/// Event store in a SQLite database.
+///
+/// Message IDs are unique in the table, so they are remembered for as long
+/// as their events are kept.
+#[derive(Clone)]
+pub struct SqliteEventStore {
+    pool: SqlitePool,
+    table_name: String,
+    cid_policy: CidPolicy,
+
+    /// Held while appending, so subscribers receive events in sequence order
+    /// and none falls between a subscription's history and its registration
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl SqliteEventStore {
+    /// Open or create the database at `database_url` (for example
+    /// `sqlite://events.db`), creating the events table if needed
+    pub async fn new(database_url: &str, table_name: &str) -> Result<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)
+            .map_err(db_error)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal);
+        let pool = SqlitePool::connect_with(options).await.map_err(db_error)?;
+
+        Self::with_pool(pool, table_name).await
+    }
+
+    /// Use an existing pool, creating the events table if needed
+    pub async fn with_pool(pool: SqlitePool, table_name: &str) -> Result<Self> {
+        validate_table_name(table_name)?;
+
+        let statements = [
+            format!(
+                r#"
+                CREATE TABLE IF NOT EXISTS {table} (
+                    sequence INTEGER PRIMARY KEY AUTOINCREMENT,
+                    aggregate_id TEXT NOT NULL,
+                    aggregate_type TEXT NOT NULL,
+                    version INTEGER NOT NULL,
+                    event_type TEXT NOT NULL,
+                    message_id TEXT NOT NULL UNIQUE,
+                    correlation_id TEXT NOT NULL,
+                    causation_id TEXT,
+                    cid TEXT NOT NULL,
+                    parent_cid TEXT,
+                    timestamp_ns INTEGER NOT NULL,
+                    body TEXT NOT NULL,
+                    UNIQUE (aggregate_id, version)
+                )
+                "#,
+                table = table_name
+            ),
+            format!("CREATE INDEX IF NOT EXISTS {t}_correlation ON {t} (correlation_id)", t = table_name),
+            format!("CREATE INDEX IF NOT EXISTS {t}_category ON {t} (aggregate_type, sequence)", t = table_name),
+            format!("CREATE INDEX IF NOT EXISTS {t}_timestamp ON {t} (timestamp_ns)", t = table_name),
+            format!("CREATE INDEX IF NOT EXISTS {t}_cid ON {t} (cid)", t = table_name),
+        ];
+        for statement in &statements {
+            sqlx::query(statement).execute(&pool).await.map_err(db_error)?;
+        }
+
+        Ok(Self {
+            pool,
+            table_name: table_name.to_string(),
+            cid_policy: CidPolicy::default(),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    /// Hash function and codec used for new event CIDs
+    pub fn with_cid_policy(mut self, policy: CidPolicy) -> Self {
+        self.cid_policy = policy;
+        self
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+
+    /// Events matching `filter`, in the order and number `tail` gives
+    async fn select(&self, conn: &mut SqliteConnection, filter: &str, args: Vec<Arg>, tail: &str) -> Result<Vec<StoredEvent>> {
+        select(conn, &self.table_name, filter, args, tail).await
+    }
+
+    async fn query(&self, filter: &str, args: Vec<Arg>, tail: &str) -> Result<Vec<StoredEvent>> {
+        let mut conn = self.pool.acquire().await.map_err(db_error)?;
+        self.select(&mut conn, filter, args, tail).await
+    }
+
+    /// Check and insert an event in one transaction, then notify subscribers
+    async fn insert(&self, mut event: StoredEvent, cid: Cid, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        let mut subscribers = self.subscribers.lock().await;
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+
+        let sql = format!("SELECT sequence, cid, timestamp_ns FROM {} WHERE message_id = ?", self.table_name);
+        let seen: Option<(i64, String, i64)> = sqlx::query_as(&sql)
+            .bind(&event.header.message_id)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        if let Some((sequence, cid, timestamp_ns)) = seen {
+            return Ok(EventMetadata {
+                sequence: sequence as u64,
+                cid: Cid::try_from(cid.as_str()).ok(),
+                timestamp: chrono::Utc.timestamp_nanos(timestamp_ns),
+                duplicate: true,
+            });
+        }
+
+        let latest = self
+            .select(
+                &mut tx,
+                "aggregate_id = ?",
+                vec![Arg::Text(event.aggregate_id.clone())],
+                "ORDER BY sequence DESC LIMIT 1",
+            )
+            .await?
+            .pop();
+        check_append(latest.as_ref(), &event, parent_cid.as_ref())?;
+
+        let sql = format!("SELECT COALESCE(MAX(version), 0) FROM {} WHERE aggregate_id = ?", self.table_name);
+        let (version,): (i64,) = sqlx::query_as(&sql)
+            .bind(&event.aggregate_id)
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        let sql = format!(
+            r#"
+            INSERT INTO {} (aggregate_id, aggregate_type, version, event_type, message_id,
+                correlation_id, causation_id, cid, parent_cid, timestamp_ns, body)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            self.table_name
+        );
+        let result = sqlx::query(&sql)
+            .bind(&event.aggregate_id)
+            .bind(event.category())
+            .bind(version + 1)
+            .bind(&event.event_type)
+            .bind(&event.header.message_id)
+            .bind(&event.header.correlation_id)
+            .bind(&event.header.causation_id)
+            .bind(cid.to_string())
+            .bind(&event.parent_cid)
+            .bind(timestamp_ns(event.timestamp))
+            .bind(serde_json::to_string(&event)?)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        tx.commit().await.map_err(db_error)?;
+
+        event.sequence = result.last_insert_rowid() as u64;
+        subscribers.retain(|subscriber| {
+            !subscriber.scope.matches(&event) || subscriber.sender.unbounded_send(event.clone()).is_ok()
+        });
+
+        Ok(EventMetadata {
+            sequence: event.sequence,
+            cid: Some(cid),
+            timestamp: event.timestamp,
+            duplicate: false,
+        })
+    }
+}
+
+#[async_trait]
+impl EventStore for SqliteEventStore {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        let mut stored_event = StoredEvent {
+            sequence: 0,
+            aggregate_id: event.aggregate_id,
+            aggregate_type: Some(event.aggregate_type),
+            event_type: event.event_type,
+            event_data: event.event_data,
+            payload_cid: None,
+            header: event.header,
+            cid: None,
+            parent_cid: parent_cid.map(|c| c.to_string()),
+            timestamp: chrono::Utc::now(),
+        };
+        let cid = self.cid_policy.cid(&stored_event.canonical_bytes()?);
+        stored_event.cid = Some(cid.to_string());
+
+        self.insert(stored_event, cid, parent_cid).await
+    }
+
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        let cid = event.verified_cid()?;
+        let parent_cid = event.parent_cid_value()?;
+
+        self.insert(event, cid, parent_cid).await
+    }
+
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        // Fetch a page at a time, resuming after the last sequence yielded
+        let store = self.clone();
+        let aggregate_id = aggregate_id.to_string();
+
+        let events = futures::stream::try_unfold(
+            (from_sequence, VecDeque::new()),
+            move |(from, mut page): (u64, VecDeque<StoredEvent>)| {
+                let store = store.clone();
+                let aggregate_id = aggregate_id.clone();
+                async move {
+                    if page.is_empty() {
+                        page = store
+                            .query(
+                                "aggregate_id = ? AND sequence >= ?",
+                                vec![Arg::Text(aggregate_id), Arg::Int(to_i64(from))],
+                                &format!("ORDER BY sequence LIMIT {}", PAGE_SIZE),
+                            )
+                            .await?
+                            .into();
+                    }
+                    let Some(event) = page.pop_front() else {
+                        return Ok(None);
+                    };
+                    let next = event.sequence + 1;
+                    Ok::<_, EventStoreError>(Some((event, (next, page))))
+                }
+            },
+        );
+
+        Ok(Box::pin(events))
+    }
+
+    async fn get_events(
+        &self,
+        aggregate_id: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.query(
+            "aggregate_id = ? AND sequence >= ?",
+            vec![Arg::Text(aggregate_id.to_string()), Arg::Int(to_i64(from_sequence))],
+            &format!("ORDER BY sequence LIMIT {}", to_i64(limit as u64)),
+        )
+        .await
+    }
+
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.query(
+            "aggregate_id = ? AND sequence <= ?",
+            vec![
+                Arg::Text(aggregate_id.to_string()),
+                Arg::Int(from_sequence.map_or(i64::MAX, to_i64)),
+            ],
+            &format!("ORDER BY sequence DESC LIMIT {}", to_i64(limit as u64)),
+        )
+        .await
+    }
+
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        let (filter, mut args) = scope_filter(&scope);
+        args.push(Arg::Int(timestamp_ns(from)));
+        args.push(Arg::Int(timestamp_ns(to)));
+
+        let events = self
+            .query(
+                &format!("{} AND timestamp_ns >= ? AND timestamp_ns < ?", filter),
+                args,
+                "ORDER BY sequence",
+            )
+            .await?;
+        Ok(Box::pin(futures::stream::iter(events.into_iter().map(Ok))))
+    }
+
+    async fn get_events_by_correlation(
+        &self,
+        correlation_id: &str,
+    ) -> Result<Vec<StoredEvent>> {
+        self.query("correlation_id = ?", vec![Arg::Text(correlation_id.to_string())], "ORDER BY sequence")
+            .await
+    }
+
+    async fn read_category(
+        &self,
+        aggregate_type: &str,
+        from_sequence: u64,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.query(
+            "aggregate_type = ? AND sequence >= ?",
+            vec![Arg::Text(aggregate_type.to_string()), Arg::Int(to_i64(from_sequence))],
+            &format!("ORDER BY sequence LIMIT {}", to_i64(limit as u64)),
+        )
+        .await
+    }
+
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        let sql = format!("DELETE FROM {} WHERE aggregate_id = ? AND sequence < ?", self.table_name);
+        let result = sqlx::query(&sql)
+            .bind(aggregate_id)
+            .bind(to_i64(before_sequence))
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        Ok(result.rows_affected())
+    }
+
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        let sql = format!(
+            r#"
+            SELECT aggregate_type, aggregate_id, MIN(sequence), MAX(sequence), COUNT(*),
+                MIN(timestamp_ns), MAX(timestamp_ns)
+            FROM {}
+            GROUP BY aggregate_type, aggregate_id
+            "#,
+            self.table_name
+        );
+        let rows: Vec<(String, String, i64, i64, i64, i64, i64)> =
+            sqlx::query_as(&sql).fetch_all(&self.pool).await.map_err(db_error)?;
+
+        let keys = rows.iter().map(|row| AggregateKey::new(&row.0, &row.1)).collect();
+        let (keys, next) = query.page(keys)?;
+
+        let head_sql = format!("SELECT cid FROM {} WHERE sequence = ?", self.table_name);
+        let mut aggregates = Vec::with_capacity(keys.len());
+        for key in keys {
+            let Some(row) = rows.iter().find(|row| row.0 == key.aggregate_type && row.1 == key.aggregate_id) else {
+                continue;
+            };
+            let head_cid: Option<(String,)> = sqlx::query_as(&head_sql)
+                .bind(row.3)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(db_error)?;
+
+            aggregates.push(AggregateInfo {
+                aggregate_id: key.aggregate_id,
+                aggregate_type: key.aggregate_type,
+                first_sequence: row.2 as u64,
+                last_sequence: row.3 as u64,
+                event_count: row.4 as u64,
+                head_cid: head_cid.map(|(cid,)| cid),
+                created_at: chrono::Utc.timestamp_nanos(row.5),
+                updated_at: chrono::Utc.timestamp_nanos(row.6),
+            });
+        }
+
+        Ok(AggregatePage { aggregates, next })
+    }
+
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        let (sender, receiver) = mpsc::unbounded();
+
+        // Queue history and register while appends are held back, so no
+        // append can fall between the two
+        let mut subscribers = self.subscribers.lock().await;
+        let (filter, mut args) = scope_filter(&scope);
+        let history = match from {
+            SubscribeFrom::Beginning => self.query(&filter, args, "ORDER BY sequence").await?,
+            SubscribeFrom::Sequence(sequence) => {
+                args.push(Arg::Int(to_i64(sequence)));
+                self.query(&format!("{} AND sequence >= ?", filter), args, "ORDER BY sequence").await?
+            }
+            SubscribeFrom::Timestamp(timestamp) => {
+                args.push(Arg::Int(timestamp_ns(timestamp)));
+                self.query(&format!("{} AND timestamp_ns >= ?", filter), args, "ORDER BY sequence").await?
+            }
+            SubscribeFrom::Last => self.query(&filter, args, "ORDER BY sequence DESC LIMIT 1").await?,
+            SubscribeFrom::New => Vec::new(),
+        };
+        for event in history {
+            let _ = sender.unbounded_send(event);
+        }
+        subscribers.push(Subscriber { scope, sender });
+
+        Ok(Box::new(receiver))
+    }
+}
+
+async fn select(
+    conn: &mut SqliteConnection,
+    table_name: &str,
+    filter: &str,
+    args: Vec<Arg>,
+    tail: &str,
+) -> Result<Vec<StoredEvent>> {
+    let sql = format!("SELECT sequence, body FROM {} WHERE {} {}", table_name, filter, tail);
+    let mut query = sqlx::query_as::<_, (i64, String)>(&sql);
+    for arg in args {
+        query = match arg {
+            Arg::Text(value) => query.bind(value),
+            Arg::Int(value) => query.bind(value),
+        };
+    }
+
+    let rows = query.fetch_all(conn).await.map_err(db_error)?;
+    rows.into_iter()
+        .map(|(sequence, body)| {
+            let mut event: StoredEvent = serde_json::from_str(&body)?;
+            event.sequence = sequence as u64;
+            Ok(event)
+        })
+        .collect()
+}
+
+/// SQL condition selecting a scope, with its parameters
+fn scope_filter(scope: &EventScope) -> (String, Vec<Arg>) {
+    match scope {
+        EventScope::All => ("1 = 1".to_string(), Vec::new()),
+        EventScope::Aggregate(aggregate_id) => ("aggregate_id = ?".to_string(), vec![Arg::Text(aggregate_id.clone())]),
+        EventScope::Category(aggregate_type) => {
+            ("aggregate_type = ?".to_string(), vec![Arg::Text(aggregate_type.clone())])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    async fn store() -> SqliteEventStore {
+        let path = std::env::temp_dir().join(format!("cim-events-{}.db", uuid::Uuid::new_v4()));
+        SqliteEventStore::new(&format!("sqlite://{}", path.display()), "events").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn versions_are_numbered_per_aggregate_in_global_order() {
+        let store = store().await;
+        let first = store.append(NewEvent::new("order-1", "Placed", json!({})), None).await.unwrap();
+        store.append(NewEvent::new("order-2", "Placed", json!({})), None).await.unwrap();
+        store.append(NewEvent::new("order-1", "Shipped", json!({})), first.cid).await.unwrap();
+
+        let rows: Vec<(i64, String, i64)> = sqlx::query_as("SELECT sequence, aggregate_id, version FROM events ORDER BY sequence")
+            .fetch_all(store.pool())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            rows,
+            vec![(1, "order-1".to_string(), 1), (2, "order-2".to_string(), 1), (3, "order-1".to_string(), 2)]
+        );
+        assert!(store.validate_cid_chain("order-1").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_taken_version_is_a_concurrent_modification() {
+        let store = store().await;
+        store.append(NewEvent::new("order-1", "Placed", json!({})), None).await.unwrap();
+
+        // A writer that read the same head as another tries the same version
+        let insert = sqlx::query(
+            "INSERT INTO events (aggregate_id, aggregate_type, version, event_type, message_id, correlation_id, cid, timestamp_ns, body) \
+             VALUES ('order-1', 'Order', 1, 'Placed', 'other', 'other', 'cid', 0, '{}')",
+        )
+        .execute(store.pool())
+        .await;
+
+        assert!(matches!(insert.map_err(db_error), Err(EventStoreError::ConcurrentModification)));
+        assert!(validate_table_name("events; DROP TABLE events").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use cim_subject::SubjectBuilder;
+
+use crate::domain::DEFAULT_AGGREGATE_TYPE;
+use crate::event_store::{EventStoreError, Result};
+
+/// Subject hierarchy an event store publishes and reads under.
+///
+/// Without a tenant the hierarchy is `{prefix}.{category}.{aggregate}.{event}`,
+/// where the category is the aggregate type. With a tenant (and optionally a
+/// domain) it becomes `{tenant}.{domain}.{prefix}.{category}.{aggregate}.{event}`,
+/// so stores sharing one NATS account never see each other's events.
+///
+/// Stores written before categories existed used `{root}.{aggregate}.{event}`.
+/// Those events have no aggregate type and belong to the default category;
+/// the `legacy_*` filters find them. Filters for the current layout match
+/// exactly three tokens below the root, so they never pick up legacy subjects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubjectNamespace {
+    tenant: Option<String>,
+    domain: Option<String>,
+    prefix: String,
+}
+
+impl SubjectNamespace {
+    /// Unscoped namespace rooted at `prefix`
+    pub fn new(prefix: &str) -> Self {
+        Self {
+            tenant: None,
+            domain: None,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    pub fn with_tenant(mut self, tenant: &str) -> Self {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    pub fn with_domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Whether the namespace is scoped to a tenant
+    pub fn is_isolated(&self) -> bool {
+        self.tenant.is_some()
+    }
+
+    /// Root subject all events in the namespace live under
+    pub fn root(&self) -> String {
+        [self.tenant.as_deref(), self.domain.as_deref(), Some(self.prefix.as_str())]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Subject builder rooted at one aggregate category of the namespace
+    pub fn category_builder(&self, aggregate_type: &str) -> Result<SubjectBuilder> {
+        Ok(SubjectBuilder::new(&format!("{}.{}", self.root(), encode_token(aggregate_type)?)))
+    }
+
+    /// Filter matching every event in the namespace
+    pub fn all_events(&self) -> String {
+        format!("{}.>", self.root())
+    }
+
+    /// Filter matching every event of one aggregate, whatever its category
+    pub fn aggregate_filter(&self, aggregate_id: &str) -> Result<String> {
+        Ok(format!("{}.*.{}.*", self.root(), encode_token(aggregate_id)?))
+    }
+
+    /// Filter matching every event of every aggregate of one type
+    pub fn category_filter(&self, aggregate_type: &str) -> Result<String> {
+        Ok(format!("{}.{}.*.*", self.root(), encode_token(aggregate_type)?))
+    }
+
+    /// Filter matching every event of one aggregate of one type
+    pub fn instance_filter(&self, aggregate_type: &str, aggregate_id: &str) -> Result<String> {
+        Ok(format!(
+            "{}.{}.{}.*",
+            self.root(),
+            encode_token(aggregate_type)?,
+            encode_token(aggregate_id)?
+        ))
+    }
+
+    /// Filter matching one aggregate's events in the legacy layout
+    pub fn legacy_aggregate_filter(&self, aggregate_id: &str) -> Result<String> {
+        Ok(format!("{}.{}.*", self.root(), encode_token(aggregate_id)?))
+    }
+
+    /// Filter matching every event in the legacy layout
+    pub fn legacy_events(&self) -> String {
+        format!("{}.*.*", self.root())
+    }
+
+    /// Filters matching one aggregate's events in either layout. With the
+    /// type known, aggregates of other types sharing the ID are left out.
+    pub fn aggregate_filters(&self, aggregate_type: Option<&str>, aggregate_id: &str) -> Result<Vec<String>> {
+        let current = match aggregate_type {
+            Some(aggregate_type) => self.instance_filter(aggregate_type, aggregate_id)?,
+            None => self.aggregate_filter(aggregate_id)?,
+        };
+
+        if aggregate_type.is_none_or(|aggregate_type| aggregate_type == DEFAULT_AGGREGATE_TYPE) {
+            Ok(vec![current, self.legacy_aggregate_filter(aggregate_id)?])
+        } else {
+            Ok(vec![current])
+        }
+    }
+
+    /// Filters matching a category's events in either layout
+    pub fn category_filters(&self, aggregate_type: &str) -> Result<Vec<String>> {
+        let current = self.category_filter(aggregate_type)?;
+
+        if aggregate_type == DEFAULT_AGGREGATE_TYPE {
+            Ok(vec![current, self.legacy_events()])
+        } else {
+            Ok(vec![current])
+        }
+    }
+
+    /// Split an event subject into its decoded aggregate type, aggregate ID
+    /// and event type; `None` if it is not an event subject of the namespace.
+    /// Legacy subjects report the default aggregate type.
+    pub fn parse_event_subject(&self, subject: &str) -> Result<Option<(String, String, String)>> {
+        let Some(rest) = subject.strip_prefix(self.root().as_str()).and_then(|rest| rest.strip_prefix('.')) else {
+            return Ok(None);
+        };
+
+        let tokens: Vec<&str> = rest.split('.').collect();
+        let (aggregate_type, aggregate_id, event_type) = match tokens.as_slice() {
+            [aggregate_type, aggregate_id, event_type] => (decode_token(aggregate_type)?, aggregate_id, event_type),
+            [aggregate_id, event_type] => (DEFAULT_AGGREGATE_TYPE.to_string(), aggregate_id, event_type),
+            _ => return Ok(None),
+        };
+
+        Ok(Some((aggregate_type, decode_token(aggregate_id)?, decode_token(event_type)?)))
+    }
+
+    /// Whether a concrete subject or filter falls inside the namespace
+    pub fn contains(&self, subject: &str) -> bool {
+        subject
+            .strip_prefix(self.root().as_str())
+            .map(|rest| rest.starts_with('.'))
+            .unwrap_or(false)
+    }
+
+    /// Reject a subject from outside the namespace
+    pub fn ensure_contains(&self, subject: &str) -> Result<()> {
+        if self.contains(subject) {
+            Ok(())
+        } else {
+            Err(EventStoreError::NamespaceViolation(format!(
+                "Subject {} is outside namespace {}",
+                subject,
+                self.root()
+            )))
+        }
+    }
+
+    /// Check every namespace token is a single, literal subject token
+    pub fn validate(&self) -> Result<()> {
+        let is_literal_token = |token: &str| {
+            !token.is_empty() && !token.contains(|c: char| c.is_whitespace() || matches!(c, '.' | '*' | '>'))
+        };
+
+        for (name, token) in [("tenant", &self.tenant), ("domain", &self.domain)] {
+            if let Some(token) = token {
+                if !is_literal_token(token) {
+                    return Err(EventStoreError::InvalidConfig(format!("Invalid {}: {:?}", name, token)));
+                }
+            }
+        }
+
+        if self.domain.is_some() && self.tenant.is_none() {
+            return Err(EventStoreError::InvalidConfig(
+                "A domain requires a tenant".to_string(),
+            ));
+        }
+
+        if !self.prefix.split('.').all(is_literal_token) {
+            return Err(EventStoreError::InvalidConfig(format!(
+                "Invalid subject prefix: {:?}",
+                self.prefix
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Encode an aggregate ID or event type as a single subject token.
+///
+/// `.`, `*`, `>`, `%`, whitespace and control characters are percent-encoded
+/// byte by byte, so the token can never split into several tokens or act as a
+/// wildcard. Everything else passes through unchanged, which keeps the
+/// subjects of existing IDs as they were.
+pub fn encode_token(value: &str) -> Result<String> {
+    if value.is_empty() {
+        return Err(EventStoreError::InvalidSubjectToken(
+            "Subject tokens cannot be empty".to_string(),
+        ));
+    }
+
+    let mut token = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '.' | '*' | '>' | '%') || c.is_whitespace() || c.is_control() {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                token.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            token.push(c);
+        }
+    }
+
+    Ok(token)
+}
+
+/// Reverse [`encode_token`]
+pub fn decode_token(token: &str) -> Result<String> {
+    let invalid = || EventStoreError::InvalidSubjectToken(format!("Malformed subject token: {:?}", token));
+
+    let mut bytes = Vec::with_capacity(token.len());
+    let mut rest = token.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unscoped_namespace_keeps_legacy_subjects() {
+        let namespace = SubjectNamespace::new("events");
+
+        assert_eq!(namespace.all_events(), "events.>");
+        assert_eq!(namespace.aggregate_filter("order-1").unwrap(), "events.*.order-1.*");
+        assert_eq!(namespace.category_filter("Order").unwrap(), "events.Order.*.*");
+        assert_eq!(namespace.legacy_aggregate_filter("order-1").unwrap(), "events.order-1.*");
+    }
+
+    #[test]
+    fn legacy_subjects_are_read_only_where_the_type_allows() {
+        let namespace = SubjectNamespace::new("events");
+
+        assert_eq!(
+            namespace.aggregate_filters(None, "o-1").unwrap(),
+            vec!["events.*.o-1.*".to_string(), "events.o-1.*".to_string()]
+        );
+        assert_eq!(namespace.aggregate_filters(Some("Order"), "o-1").unwrap(), vec!["events.Order.o-1.*".to_string()]);
+        assert_eq!(
+            namespace.aggregate_filters(Some(DEFAULT_AGGREGATE_TYPE), "o-1").unwrap(),
+            vec!["events.aggregate.o-1.*".to_string(), "events.o-1.*".to_string()]
+        );
+        assert_eq!(namespace.category_filters("Order").unwrap(), vec!["events.Order.*.*".to_string()]);
+        assert_eq!(
+            namespace.parse_event_subject("events.o-1.OrderPlaced").unwrap(),
+            Some((DEFAULT_AGGREGATE_TYPE.to_string(), "o-1".to_string(), "OrderPlaced".to_string()))
+        );
+    }
+
+    #[test]
+    fn tenant_namespaces_are_disjoint() {
+        let acme = SubjectNamespace::new("events").with_tenant("acme").with_domain("sales");
+        let globex = SubjectNamespace::new("events").with_tenant("globex").with_domain("sales");
+
+        assert_eq!(acme.aggregate_filter("order-1").unwrap(), "acme.sales.events.*.order-1.*");
+        assert!(acme.contains("acme.sales.events.Order.order-1.OrderCreated"));
+        assert!(!acme.contains("globex.sales.events.Order.order-1.OrderCreated"));
+        assert!(!acme.contains("acme.sales.eventsx.Order.order-1.OrderCreated"));
+        assert!(!globex.contains(&acme.all_events()));
+    }
+
+    #[test]
+    fn validate_rejects_wildcard_tenants() {
+        assert!(SubjectNamespace::new("events").with_tenant("*").validate().is_err());
+        assert!(SubjectNamespace::new("events").with_tenant("a.b").validate().is_err());
+        assert!(SubjectNamespace::new("events").with_domain("sales").validate().is_err());
+        assert!(SubjectNamespace::new("events").with_tenant("acme").validate().is_ok());
+    }
+
+    #[test]
+    fn tokens_round_trip_and_never_contain_wildcards() {
+        for id in ["order-1", "a.b", "*", "orders.>", "with space", "50%", "ünïcödé\t"] {
+            let token = encode_token(id).unwrap();
+
+            assert!(!token.contains(|c: char| matches!(c, '.' | '*' | '>') || c.is_whitespace()));
+            assert_eq!(decode_token(&token).unwrap(), id);
+        }
+
+        assert_eq!(encode_token("order-1").unwrap(), "order-1");
+        assert_eq!(encode_token("a.b").unwrap(), "a%2Eb");
+        assert!(encode_token("").is_err());
+        assert!(decode_token("bad%2").is_err());
+    }
+
+    #[test]
+    fn hostile_ids_stay_inside_their_aggregate() {
+        let namespace = SubjectNamespace::new("events");
+
+        assert_eq!(namespace.aggregate_filter("*").unwrap(), "events.*.%2A.*");
+        assert_eq!(namespace.aggregate_filter("a.>").unwrap(), "events.*.a%2E%3E.*");
+        assert_eq!(namespace.category_filter("*").unwrap(), "events.%2A.*.*");
+    }
+
+    #[test]
+    fn event_subjects_parse_back_to_their_parts() {
+        let namespace = SubjectNamespace::new("events").with_tenant("acme");
+
+        assert_eq!(
+            namespace.parse_event_subject("acme.events.Order.a%2Eb.OrderPlaced").unwrap(),
+            Some(("Order".to_string(), "a.b".to_string(), "OrderPlaced".to_string()))
+        );
+        assert_eq!(namespace.instance_filter("Order", "a.b").unwrap(), "acme.events.Order.a%2Eb.*");
+        assert_eq!(namespace.parse_event_subject("globex.events.Order.o-1.OrderPlaced").unwrap(), None);
+        assert_eq!(namespace.parse_event_subject("acme.events.Order.o-1.OrderPlaced.extra").unwrap(), None);
+    }
+}
@@ -0,0 +1,122 @@
+//! Test doubles shared by the unit tests.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use async_nats::jetstream::context::{RequestError, RequestErrorKind};
+use async_trait::async_trait;
+use cid::Cid;
+use futures::Stream;
+
+use crate::catalog::{AggregatePage, AggregateQuery};
+use crate::event_store::{
+    EventMetadata, EventScope, EventStore, EventStoreError, NewEvent, ReadStream, Result, StoredEvent,
+    SubscribeFrom,
+};
+use crate::memory::InMemoryEventStore;
+
+/// An error the store could not get an answer for, as a retry would see it
+pub(crate) fn transient() -> EventStoreError {
+    RequestError::from(RequestErrorKind::TimedOut).into()
+}
+
+/// In-memory store whose appends fail with a transient error while it is
+/// offline, or for a set number of attempts
+pub(crate) struct FaultyStore {
+    inner: InMemoryEventStore,
+    offline: AtomicBool,
+    failures: AtomicU32,
+}
+
+impl FaultyStore {
+    /// Wrap a store, sharing its events with the clone it was given
+    pub(crate) fn new(inner: &InMemoryEventStore) -> Self {
+        Self {
+            inner: inner.clone(),
+            offline: AtomicBool::new(false),
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Fail the next `failures` appends
+    pub(crate) fn failing(failures: u32) -> Self {
+        let store = Self::new(&InMemoryEventStore::new());
+        store.failures.store(failures, Ordering::SeqCst);
+        store
+    }
+
+    /// Fail every append until the store is back online
+    pub(crate) fn set_offline(&self, offline: bool) {
+        self.offline.store(offline, Ordering::SeqCst);
+    }
+
+    fn check(&self) -> Result<()> {
+        let failing = self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok();
+        if failing || self.offline.load(Ordering::SeqCst) {
+            return Err(transient());
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EventStore for FaultyStore {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        self.check()?;
+        self.inner.append(event, parent_cid).await
+    }
+
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        self.check()?;
+        self.inner.append_stored_event(event).await
+    }
+
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        self.inner.read_stream(aggregate_id, from_sequence).await
+    }
+
+    async fn get_events(&self, aggregate_id: &str, from_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.inner.get_events(aggregate_id, from_sequence, limit).await
+    }
+
+    async fn get_events_by_correlation(&self, correlation_id: &str) -> Result<Vec<StoredEvent>> {
+        self.inner.get_events_by_correlation(correlation_id).await
+    }
+
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.inner.read_backward(aggregate_id, from_sequence, limit).await
+    }
+
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        self.inner.read_between(scope, from, to).await
+    }
+
+    async fn read_category(&self, aggregate_type: &str, from_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.inner.read_category(aggregate_type, from_sequence, limit).await
+    }
+
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        self.inner.purge(aggregate_id, before_sequence).await
+    }
+
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        self.inner.list_aggregates(query).await
+    }
+
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        self.inner.subscribe_with(scope, from).await
+    }
+}
@@ -0,0 +1,577 @@
+//! Durable local buffering of appends while the backing store is unreachable.
+//!
+//! Edge nodes keep accepting appends while disconnected: events are given
+//! their CIDs locally, written to a file-backed write-ahead log and replayed
+//! to the backing store in order once it is reachable again. A replayed
+//! event keeps its CID, so events appended offline can chain onto each other.
+//! If the remote aggregate moved on in the meantime the replay is refused;
+//! the event, and every later buffered event of that aggregate, is kept in
+//! the log as a conflict for the application to resolve.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use cid::Cid;
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::catalog::{AggregatePage, AggregateQuery};
+use crate::cid_policy::CidPolicy;
+use crate::event_store::{
+    EventMetadata, EventScope, EventStore, EventStoreError, NewEvent, ReadStream, Result, StoredEvent,
+    SubscribeFrom,
+};
+
+/// One buffered event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalEntry {
+    pub event: StoredEvent,
+
+    /// Why the backing store refused the event, once it has
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict: Option<String>,
+}
+
+/// Append-only file of buffered events, one JSON line each
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    entries: Vec<WalEntry>,
+}
+
+impl WriteAheadLog {
+    /// Open or create a log, dropping a final line torn by a crash
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let contents = match tokio::fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(EventStoreError::Wal(e.to_string())),
+        };
+
+        let mut entries = Vec::new();
+        let mut lines = contents.lines().peekable();
+        while let Some(line) = lines.next() {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) if lines.peek().is_none() => break,
+                Err(e) => return Err(EventStoreError::Wal(format!("Corrupt entry in {}: {}", path.display(), e))),
+            }
+        }
+
+        let mut wal = Self { path, entries };
+        // Rewrite so a torn line is not followed by new entries
+        wal.rewrite().await?;
+        Ok(wal)
+    }
+
+    pub fn entries(&self) -> &[WalEntry] {
+        &self.entries
+    }
+
+    /// Entries still waiting to be replayed
+    pub fn pending(&self) -> impl Iterator<Item = &WalEntry> {
+        self.entries.iter().filter(|entry| entry.conflict.is_none())
+    }
+
+    /// Aggregates with a refused entry; their later entries are refused too
+    fn conflicted_aggregates(&self) -> HashSet<String> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.conflict.is_some())
+            .map(|entry| entry.event.aggregate_id.clone())
+            .collect()
+    }
+
+    /// Record the outcome of replaying pending entries: drop the ones the
+    /// backing store accepted and mark the ones it refused, in one rewrite.
+    ///
+    /// Outcomes are matched to the earliest pending entry with the same
+    /// message ID, so entries buffered during the replay are left alone.
+    async fn settle(&mut self, outcomes: Vec<(String, Option<String>)>, report: &mut SyncReport) -> Result<()> {
+        if outcomes.is_empty() {
+            return Ok(());
+        }
+
+        for (message_id, conflict) in outcomes {
+            let Some(index) = self
+                .entries
+                .iter()
+                .position(|entry| entry.conflict.is_none() && entry.event.header.message_id == message_id)
+            else {
+                continue;
+            };
+
+            match conflict {
+                None => {
+                    self.entries.remove(index);
+                }
+                Some(reason) => {
+                    self.entries[index].conflict = Some(reason);
+                    report.conflicts.push(self.entries[index].clone());
+                }
+            }
+        }
+
+        self.rewrite().await
+    }
+
+    /// Durably append an entry
+    async fn push(&mut self, entry: WalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| EventStoreError::Wal(e.to_string()))?;
+        file.write_all(&line).await.map_err(|e| EventStoreError::Wal(e.to_string()))?;
+        file.sync_data().await.map_err(|e| EventStoreError::Wal(e.to_string()))?;
+
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Replace the file with the current entries
+    async fn rewrite(&mut self) -> Result<()> {
+        let mut contents = Vec::new();
+        for entry in &self.entries {
+            contents.extend(serde_json::to_vec(entry)?);
+            contents.push(b'\n');
+        }
+
+        // Write to a temporary file first so a crash never leaves a partial log
+        let tmp = self.path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+        let mut file = tokio::fs::File::create(&tmp)
+            .await
+            .map_err(|e| EventStoreError::Wal(e.to_string()))?;
+        file.write_all(&contents).await.map_err(|e| EventStoreError::Wal(e.to_string()))?;
+        file.sync_data().await.map_err(|e| EventStoreError::Wal(e.to_string()))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .map_err(|e| EventStoreError::Wal(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Outcome of replaying the log
+#[derive(Debug, Clone, Default)]
+pub struct SyncReport {
+    /// Receipts of the events the backing store accepted, in order
+    pub replayed: Vec<EventMetadata>,
+
+    /// Entries newly refused by the backing store
+    pub conflicts: Vec<WalEntry>,
+
+    /// Entries still waiting because the backing store became unreachable
+    pub pending: usize,
+}
+
+/// Event store wrapper that buffers appends in a write-ahead log while the
+/// backing store is unreachable.
+///
+/// A buffered append returns a receipt with sequence 0 and the event's final
+/// CID. Buffered events are not visible to reads until they are replayed,
+/// which happens on the next append or an explicit [`sync`](Self::sync).
+pub struct BufferedEventStore<S> {
+    inner: S,
+
+    /// Held only to read or change the log, never across calls to the
+    /// backing store
+    wal: Mutex<WriteAheadLog>,
+
+    /// Held while replaying, so buffered events are sent once and in order
+    replaying: Mutex<()>,
+
+    cid_policy: CidPolicy,
+}
+
+impl<S: EventStore> BufferedEventStore<S> {
+    /// Wrap a store, buffering into the log at `wal_path`
+    pub async fn open(inner: S, wal_path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self {
+            inner,
+            wal: Mutex::new(WriteAheadLog::open(wal_path).await?),
+            replaying: Mutex::new(()),
+            cid_policy: CidPolicy::default(),
+        })
+    }
+
+    /// Hash function and codec for CIDs of buffered events
+    pub fn with_cid_policy(mut self, policy: CidPolicy) -> Self {
+        self.cid_policy = policy;
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Events waiting to be replayed, in order
+    pub async fn pending(&self) -> Vec<StoredEvent> {
+        self.wal.lock().await.pending().map(|entry| entry.event.clone()).collect()
+    }
+
+    /// Buffered events the backing store refused
+    pub async fn conflicts(&self) -> Vec<WalEntry> {
+        let wal = self.wal.lock().await;
+        wal.entries().iter().filter(|entry| entry.conflict.is_some()).cloned().collect()
+    }
+
+    /// Remove an aggregate's conflicting events from the log, returning them
+    /// so they can be re-applied on top of the remote history
+    pub async fn take_conflicts(&self, aggregate_id: &str) -> Result<Vec<StoredEvent>> {
+        let mut wal = self.wal.lock().await;
+        let (taken, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut wal.entries)
+            .into_iter()
+            .partition(|entry| entry.conflict.is_some() && entry.event.aggregate_id == aggregate_id);
+        wal.entries = kept;
+        wal.rewrite().await?;
+
+        Ok(taken.into_iter().map(|entry| entry.event).collect())
+    }
+
+    /// Replay buffered events to the backing store, in order
+    pub async fn sync(&self) -> Result<SyncReport> {
+        let _replaying = self.replaying.lock().await;
+        self.replay().await
+    }
+
+    /// Replay unless another replay is already running, and report whether
+    /// events are still waiting, in which case new appends must queue
+    /// behind them to keep store order
+    async fn try_replay(&self) -> Result<bool> {
+        if self.wal.lock().await.pending().next().is_none() {
+            return Ok(false);
+        }
+
+        match self.replaying.try_lock() {
+            Ok(_replaying) => Ok(self.replay().await?.pending > 0),
+            Err(_) => Ok(true),
+        }
+    }
+
+    /// Send pending entries to the backing store; callers hold `replaying`.
+    ///
+    /// Pending entries are copied out of the log, replayed without holding
+    /// it, and their outcomes written back in one step, so appends keep
+    /// being buffered while a long replay runs.
+    async fn replay(&self) -> Result<SyncReport> {
+        let mut report = SyncReport::default();
+
+        loop {
+            let (batch, mut conflicted) = {
+                let wal = self.wal.lock().await;
+                (wal.pending().cloned().collect::<Vec<_>>(), wal.conflicted_aggregates())
+            };
+            if batch.is_empty() {
+                break;
+            }
+
+            let mut depth: HashMap<&str, usize> = HashMap::new();
+            for entry in &batch {
+                *depth.entry(entry.event.aggregate_id.as_str()).or_default() += 1;
+            }
+
+            let mut outcomes = Vec::with_capacity(batch.len());
+            let mut outcome = Ok(());
+            let mut offline = false;
+            for entry in &batch {
+                let message_id = entry.event.header.message_id.clone();
+
+                // Later events chain onto the refused one, so they are refused too
+                if conflicted.contains(&entry.event.aggregate_id) {
+                    outcomes.push((message_id, Some("Follows a conflicting event".to_string())));
+                    continue;
+                }
+
+                // A crash after this append but before the log is rewritten is
+                // harmless: the replay is deduplicated on the message ID, or
+                // found among the aggregate's newest events once the
+                // backing store no longer remembers the ID
+                match self.inner.append_stored_event(entry.event.clone()).await {
+                    Ok(receipt) => {
+                        report.replayed.push(receipt);
+                        outcomes.push((message_id, None));
+                    }
+                    Err(e) if is_offline(&e) => {
+                        offline = true;
+                        break;
+                    }
+                    Err(
+                        e @ (EventStoreError::InvalidCidChain(_)
+                        | EventStoreError::AggregateClosed(_)
+                        | EventStoreError::ConcurrentModification),
+                    ) => match self.already_stored(&entry.event, depth[entry.event.aggregate_id.as_str()]).await {
+                        Ok(Some(receipt)) => {
+                            report.replayed.push(receipt);
+                            outcomes.push((message_id, None));
+                        }
+                        Ok(None) => {
+                            conflicted.insert(entry.event.aggregate_id.clone());
+                            outcomes.push((message_id, Some(e.to_string())));
+                        }
+                        Err(e) if is_offline(&e) => {
+                            offline = true;
+                            break;
+                        }
+                        Err(e) => {
+                            outcome = Err(e);
+                            break;
+                        }
+                    },
+                    Err(e) => {
+                        outcome = Err(e);
+                        break;
+                    }
+                }
+            }
+
+            self.wal.lock().await.settle(outcomes, &mut report).await?;
+            outcome?;
+            if offline {
+                break;
+            }
+        }
+
+        report.pending = self.wal.lock().await.pending().count();
+        Ok(report)
+    }
+
+    /// Receipt for an event the backing store already holds among the
+    /// aggregate's newest `depth` events.
+    ///
+    /// A replay that stored events but crashed before the log was rewritten
+    /// leaves them pending; past the store's duplicate window they come back
+    /// refused, since their parents are no longer the head.
+    async fn already_stored(&self, event: &StoredEvent, depth: usize) -> Result<Option<EventMetadata>> {
+        let recent = self.inner.read_backward(&event.aggregate_id, None, depth).await?;
+
+        recent
+            .into_iter()
+            .find(|stored| stored.cid == event.cid)
+            .map(|stored| {
+                Ok(EventMetadata {
+                    sequence: stored.sequence,
+                    cid: Some(stored.verified_cid()?),
+                    timestamp: stored.timestamp,
+                    duplicate: true,
+                })
+            })
+            .transpose()
+    }
+
+    /// Buffer an event whose CID is already set
+    async fn buffer(&self, event: StoredEvent, cid: Cid) -> Result<EventMetadata> {
+        let timestamp = event.timestamp;
+        self.wal.lock().await.push(WalEntry { event, conflict: None }).await?;
+
+        Ok(EventMetadata {
+            sequence: 0,
+            cid: Some(cid),
+            timestamp,
+            duplicate: false,
+        })
+    }
+}
+
+/// Whether an error means the backing store could not be reached
+fn is_offline(error: &EventStoreError) -> bool {
+    error.is_transient() || matches!(error, EventStoreError::Unavailable(_))
+}
+
+#[async_trait]
+impl<S: EventStore> EventStore for BufferedEventStore<S> {
+    async fn append(&self, event: NewEvent, parent_cid: Option<Cid>) -> Result<EventMetadata> {
+        // Keep store order: nothing new goes through while older events wait
+        if !self.try_replay().await? {
+            match self.inner.append(event.clone(), parent_cid).await {
+                Err(e) if is_offline(&e) => {}
+                result => return result,
+            }
+        }
+
+        let mut stored_event = StoredEvent {
+            sequence: 0,
+            aggregate_id: event.aggregate_id,
+            aggregate_type: Some(event.aggregate_type),
+            event_type: event.event_type,
+            event_data: event.event_data,
+            payload_cid: None,
+            header: event.header,
+            cid: None,
+            parent_cid: parent_cid.map(|c| c.to_string()),
+            timestamp: chrono::Utc::now(),
+        };
+        let cid = self.cid_policy.cid(&stored_event.canonical_bytes()?);
+        stored_event.cid = Some(cid.to_string());
+
+        self.buffer(stored_event, cid).await
+    }
+
+    async fn append_stored_event(&self, event: StoredEvent) -> Result<EventMetadata> {
+        let cid = event.verified_cid()?;
+
+        if !self.try_replay().await? {
+            match self.inner.append_stored_event(event.clone()).await {
+                Err(e) if is_offline(&e) => {}
+                result => return result,
+            }
+        }
+
+        self.buffer(event, cid).await
+    }
+
+    async fn read_stream(&self, aggregate_id: &str, from_sequence: u64) -> Result<ReadStream> {
+        self.inner.read_stream(aggregate_id, from_sequence).await
+    }
+
+    async fn get_events(&self, aggregate_id: &str, from_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.inner.get_events(aggregate_id, from_sequence, limit).await
+    }
+
+    async fn get_events_by_correlation(&self, correlation_id: &str) -> Result<Vec<StoredEvent>> {
+        self.inner.get_events_by_correlation(correlation_id).await
+    }
+
+    async fn read_backward(
+        &self,
+        aggregate_id: &str,
+        from_sequence: Option<u64>,
+        limit: usize,
+    ) -> Result<Vec<StoredEvent>> {
+        self.inner.read_backward(aggregate_id, from_sequence, limit).await
+    }
+
+    async fn read_between(
+        &self,
+        scope: EventScope,
+        from: chrono::DateTime<chrono::Utc>,
+        to: chrono::DateTime<chrono::Utc>,
+    ) -> Result<ReadStream> {
+        self.inner.read_between(scope, from, to).await
+    }
+
+    async fn read_category(&self, aggregate_type: &str, from_sequence: u64, limit: usize) -> Result<Vec<StoredEvent>> {
+        self.inner.read_category(aggregate_type, from_sequence, limit).await
+    }
+
+    async fn purge(&self, aggregate_id: &str, before_sequence: u64) -> Result<u64> {
+        self.inner.purge(aggregate_id, before_sequence).await
+    }
+
+    async fn list_aggregates(&self, query: AggregateQuery) -> Result<AggregatePage> {
+        self.inner.list_aggregates(query).await
+    }
+
+    async fn subscribe_with(
+        &self,
+        scope: EventScope,
+        from: SubscribeFrom,
+    ) -> Result<Box<dyn Stream<Item = StoredEvent> + Send + Unpin>> {
+        self.inner.subscribe_with(scope, from).await
+    }
+
+    async fn validate_cid_chain(&self, aggregate_id: &str) -> Result<bool> {
+        self.inner.validate_cid_chain(aggregate_id).await
+    }
+
+    async fn load_event_data(&self, event: &StoredEvent) -> Result<serde_json::Value> {
+        self.inner.load_event_data(event).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryEventStore;
+    use crate::testing::FaultyStore;
+    use serde_json::json;
+
+    fn wal_path() -> PathBuf {
+        std::env::temp_dir().join(format!("cim-wal-{}.jsonl", uuid::Uuid::new_v4()))
+    }
+
+    fn line(n: u32) -> NewEvent {
+        NewEvent::new("order-1", "LineAdded", json!({ "line": n }))
+    }
+
+    #[tokio::test]
+    async fn offline_appends_replay_in_order_with_their_cids() {
+        let path = wal_path();
+        let remote = InMemoryEventStore::new();
+        let store = BufferedEventStore::open(FaultyStore::new(&remote), &path).await.unwrap();
+
+        let first = store.append(line(1), None).await.unwrap();
+        store.inner().set_offline(true);
+        let second = store.append(line(2), first.cid).await.unwrap();
+        let third = store.append(line(3), second.cid).await.unwrap();
+        assert_eq!((second.sequence, store.pending().await.len()), (0, 2));
+
+        // The log survives a restart
+        drop(store);
+        let store = BufferedEventStore::open(FaultyStore::new(&remote), &path).await.unwrap();
+        let report = store.sync().await.unwrap();
+
+        let events = store.get_events("order-1", 0, 10).await.unwrap();
+        assert_eq!(report.replayed.len(), 2);
+        assert_eq!(events[2].cid, third.cid.map(|cid| cid.to_string()));
+        assert!(store.pending().await.is_empty());
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_remote_writes_are_reported_as_conflicts() {
+        let path = wal_path();
+        let remote = InMemoryEventStore::new();
+        let store = BufferedEventStore::open(FaultyStore::new(&remote), &path).await.unwrap();
+        let head = store.append(line(1), None).await.unwrap();
+
+        store.inner().set_offline(true);
+        let local = store.append(line(2), head.cid).await.unwrap();
+        store.append(line(3), local.cid).await.unwrap();
+
+        // Another node extends the remote aggregate meanwhile
+        store.inner().set_offline(false);
+        remote.append(line(9), head.cid).await.unwrap();
+        let report = store.sync().await.unwrap();
+
+        assert_eq!(report.conflicts.len(), 2);
+        assert_eq!(report.pending, 0);
+        assert_eq!(store.take_conflicts("order-1").await.unwrap().len(), 2);
+        assert!(store.conflicts().await.is_empty());
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn events_stored_by_an_interrupted_replay_are_not_conflicts() {
+        let path = wal_path();
+        // Forget message IDs at once, as after the duplicate window
+        let remote = InMemoryEventStore::new().with_idempotency_window(std::time::Duration::ZERO);
+        let store = BufferedEventStore::open(FaultyStore::new(&remote), &path).await.unwrap();
+        let head = store.append(line(1), None).await.unwrap();
+
+        store.inner().set_offline(true);
+        let local = store.append(line(2), head.cid).await.unwrap();
+        store.append(line(3), local.cid).await.unwrap();
+
+        // A replay stored both, then crashed before rewriting the log
+        for event in store.pending().await {
+            remote.append_stored_event(event).await.unwrap();
+        }
+        store.inner().set_offline(false);
+        let report = store.sync().await.unwrap();
+
+        assert!(report.conflicts.is_empty());
+        assert_eq!(report.replayed.len(), 2);
+        assert!(report.replayed.iter().all(|receipt| receipt.duplicate));
+        assert_eq!(store.get_events("order-1", 0, 10).await.unwrap().len(), 3);
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+}
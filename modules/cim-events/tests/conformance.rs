@@ -0,0 +1,382 @@
+//! Behaviour every `EventStore` backend must share.
+//!
+//! Each check is written once against the trait and run for every backend.
+//! Checks use fresh aggregate IDs and compare sequences relative to each
+//! other, so they can share a JetStream stream with other tests. The
+//! JetStream run needs a NATS server on localhost:4222.
+
+use std::sync::Arc;
+
+use cim_events::domain::{Event, EventHeader};
+use cim_events::event_store::EventStoreError;
+use cim_events::{
+    tombstone, truncate, AggregateQuery, EventScope, EventStore, EventStoreExt, FileEventStore, InMemoryEventStore,
+    JetStreamEventStore, NewEvent, SubscribeFrom,
+};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+struct TestEvent {
+    id: String,
+    data: String,
+}
+
+impl Event for TestEvent {
+    fn event_type(&self) -> &str {
+        "TestEvent"
+    }
+
+    fn aggregate_id(&self) -> &str {
+        &self.id
+    }
+
+    fn aggregate_type(&self) -> &str {
+        "Conformance"
+    }
+}
+
+fn test_event(aggregate_id: &str, data: &str) -> TestEvent {
+    TestEvent {
+        id: aggregate_id.to_string(),
+        data: data.to_string(),
+    }
+}
+
+async fn file_store() -> FileEventStore {
+    let dir = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("conformance-{}", Uuid::new_v4()));
+    // Small segments, so the checks cross segment boundaries
+    FileEventStore::open(dir).await.unwrap().with_segment_size(2048)
+}
+
+#[cfg(feature = "sqlite")]
+async fn sqlite_store() -> cim_events::SqliteEventStore {
+    let path = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("conformance-{}.db", Uuid::new_v4()));
+    cim_events::SqliteEventStore::new(&format!("sqlite://{}", path.display()), "events")
+        .await
+        .unwrap()
+}
+
+#[cfg(feature = "postgres")]
+async fn postgres_store() -> cim_events::PostgresEventStore {
+    let url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "postgres://localhost/cim_events".to_string());
+    cim_events::PostgresEventStore::new(&url, &format!("events_{}", Uuid::new_v4().simple()))
+        .await
+        .unwrap()
+}
+
+async fn jetstream_store() -> JetStreamEventStore {
+    let client = async_nats::connect("nats://localhost:4222").await.unwrap();
+    JetStreamEventStore::new(async_nats::jetstream::new(client), "test-events")
+        .await
+        .unwrap()
+}
+
+mod checks {
+    use super::*;
+    use futures::{StreamExt, TryStreamExt};
+    use std::time::Duration;
+
+    /// Append `count` chained events, returning their receipts' CIDs
+    async fn append_chain(store: &dyn EventStore, aggregate_id: &str, count: usize) -> Vec<cid::Cid> {
+        let mut cids: Vec<cid::Cid> = Vec::new();
+        for n in 0..count {
+            let event = test_event(aggregate_id, &format!("event {}", n));
+            let receipt = store.append_event(aggregate_id, event, cids.last().copied()).await.unwrap();
+            cids.push(receipt.cid.unwrap());
+        }
+        cids
+    }
+
+    pub async fn appends_and_retrieves_events(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+
+        let receipt = store
+            .append_event(&aggregate_id, test_event(&aggregate_id, "test data"), None)
+            .await
+            .unwrap();
+        let events = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+
+        assert!(receipt.sequence > 0);
+        assert!(!receipt.duplicate);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].sequence, receipt.sequence);
+        assert_eq!(events[0].aggregate_id, aggregate_id);
+        assert_eq!(events[0].category(), "Conformance");
+        assert_eq!(events[0].cid, receipt.cid.map(|cid| cid.to_string()));
+        assert_eq!(events[0].event_data["data"], "test data");
+    }
+
+    pub async fn links_events_into_a_cid_chain(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+
+        let cids = append_chain(&*store, &aggregate_id, 3).await;
+        let events = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+
+        assert!(events[0].parent_cid.is_none());
+        assert_eq!(events[1].parent_cid, Some(cids[0].to_string()));
+        assert_eq!(events[2].parent_cid, Some(cids[1].to_string()));
+        assert!(store.validate_cid_chain(&aggregate_id).await.unwrap());
+    }
+
+    pub async fn rejects_a_stale_parent(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        let cids = append_chain(&*store, &aggregate_id, 2).await;
+
+        let result = store
+            .append_event(&aggregate_id, test_event(&aggregate_id, "stale"), Some(cids[0]))
+            .await;
+
+        assert!(matches!(result, Err(EventStoreError::InvalidCidChain(_))));
+        assert_eq!(store.get_events(&aggregate_id, 0, 10).await.unwrap().len(), 2);
+    }
+
+    pub async fn keeps_aggregate_ids_unique_across_types(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        let cids = append_chain(&*store, &aggregate_id, 2).await;
+
+        let other_type = NewEvent::new(&aggregate_id, "Shipped", serde_json::json!({})).with_aggregate_type("Shipment");
+        let result = store.append(other_type, cids.last().copied()).await;
+
+        assert!(matches!(result, Err(EventStoreError::AggregateTypeConflict(_))));
+        let events = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+        assert!(events.iter().all(|e| e.category() == "Conformance"));
+        assert!(store.validate_cid_chain(&aggregate_id).await.unwrap());
+        let shipments = store.read_category("Shipment", 0, 1000).await.unwrap();
+        assert!(shipments.iter().all(|e| e.aggregate_id != aggregate_id));
+    }
+
+    pub async fn tracks_correlation_and_causation(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        let correlation_id = Uuid::new_v4().to_string();
+        let causation_id = Uuid::new_v4().to_string();
+        let header = EventHeader::with_causation(correlation_id.clone(), causation_id.clone());
+
+        store
+            .append_event_with_header(&aggregate_id, test_event(&aggregate_id, "correlated"), header, None)
+            .await
+            .unwrap();
+        let events = store.get_events_by_correlation(&correlation_id).await.unwrap();
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].header.causation_id, Some(causation_id));
+    }
+
+    pub async fn replays_from_a_sequence(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        append_chain(&*store, &aggregate_id, 5).await;
+        let all = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+
+        let from_third = store.get_events(&aggregate_id, all[2].sequence, 10).await.unwrap();
+        let streamed: Vec<_> = store
+            .read_stream(&aggregate_id, all[2].sequence)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        let limited = store.get_events(&aggregate_id, all[2].sequence, 2).await.unwrap();
+
+        let sequences = |events: &[cim_events::StoredEvent]| events.iter().map(|e| e.sequence).collect::<Vec<_>>();
+        assert_eq!(sequences(&from_third), sequences(&all[2..]));
+        assert_eq!(sequences(&streamed), sequences(&all[2..]));
+        assert_eq!(sequences(&limited), sequences(&all[2..4]));
+    }
+
+    pub async fn reads_backward_newest_first(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        append_chain(&*store, &aggregate_id, 4).await;
+        let all = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+
+        let newest = store.read_backward(&aggregate_id, None, 2).await.unwrap();
+        let before_third = store.read_backward(&aggregate_id, Some(all[2].sequence), 10).await.unwrap();
+
+        assert_eq!(newest.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![all[3].sequence, all[2].sequence]);
+        assert_eq!(before_third.len(), 3);
+        assert!(store.read_backward(&Uuid::new_v4().to_string(), None, 10).await.unwrap().is_empty());
+    }
+
+    pub async fn stores_repeated_message_ids_once(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        let header = EventHeader::new().with_message_id(Uuid::new_v4().to_string());
+
+        let original = store
+            .append_event_with_header(&aggregate_id, test_event(&aggregate_id, "once"), header.clone(), None)
+            .await
+            .unwrap();
+        let retry = store
+            .append_event_with_header(&aggregate_id, test_event(&aggregate_id, "once"), header, None)
+            .await
+            .unwrap();
+
+        assert!(retry.duplicate);
+        assert_eq!(retry.sequence, original.sequence);
+        assert_eq!(retry.cid, original.cid);
+        assert_eq!(store.get_events(&aggregate_id, 0, 10).await.unwrap().len(), 1);
+    }
+
+    pub async fn delivers_history_then_live_events(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        let cids = append_chain(&*store, &aggregate_id, 2).await;
+
+        let scope = EventScope::Aggregate(aggregate_id.clone());
+        let mut from_start = store.subscribe_with(scope.clone(), SubscribeFrom::Beginning).await.unwrap();
+        let mut live = store.subscribe_with(scope, SubscribeFrom::New).await.unwrap();
+        store
+            .append_event(&aggregate_id, test_event(&aggregate_id, "live"), cids.last().copied())
+            .await
+            .unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            let event = tokio::time::timeout(Duration::from_secs(5), from_start.next()).await.unwrap().unwrap();
+            received.push(event.event_data["data"].as_str().unwrap().to_string());
+        }
+        let next = tokio::time::timeout(Duration::from_secs(5), live.next()).await.unwrap().unwrap();
+
+        assert_eq!(received, vec!["event 0", "event 1", "live"]);
+        assert_eq!(next.event_data["data"], "live");
+    }
+
+    pub async fn assigns_unique_sequences_to_concurrent_appends(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+
+        let handles: Vec<_> = (0..10)
+            .map(|n| {
+                let store = store.clone();
+                let aggregate_id = aggregate_id.clone();
+                tokio::spawn(async move {
+                    let event = test_event(&aggregate_id, &format!("concurrent {}", n));
+                    store.append_event(&aggregate_id, event, None).await
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+
+        let mut sequences: Vec<u64> = store
+            .get_events(&aggregate_id, 0, 20)
+            .await
+            .unwrap()
+            .iter()
+            .map(|e| e.sequence)
+            .collect();
+        sequences.dedup();
+        assert_eq!(sequences.len(), 10);
+        assert!(sequences.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    pub async fn lists_aggregates_with_their_heads(store: Arc<dyn EventStore>) {
+        let prefix = Uuid::new_v4().to_string();
+        let first = format!("{}-a", prefix);
+        let second = format!("{}-b", prefix);
+        let cids = append_chain(&*store, &first, 2).await;
+        append_chain(&*store, &second, 1).await;
+
+        let query = AggregateQuery::new().with_type("Conformance").with_prefix(&prefix).with_limit(1);
+        let page = store.list_aggregates(query.clone()).await.unwrap();
+        let rest = store.list_aggregates(query.after(&page.next.clone().unwrap())).await.unwrap();
+
+        assert_eq!(page.aggregates[0].aggregate_id, first);
+        assert_eq!(page.aggregates[0].event_count, 2);
+        assert_eq!(page.aggregates[0].head_cid, Some(cids[1].to_string()));
+        assert_eq!(rest.aggregates.iter().map(|a| a.aggregate_id.clone()).collect::<Vec<_>>(), vec![second]);
+        assert!(rest.next.is_none());
+    }
+
+    pub async fn keeps_truncated_chains_verifiable(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        append_chain(&*store, &aggregate_id, 4).await;
+        let all = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+
+        let truncation = truncate(&*store, &aggregate_id, all[2].sequence).await.unwrap();
+        let kept = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+
+        assert_eq!(truncation.removed, 2);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(kept[0].sequence, all[2].sequence);
+        assert!(store.validate_cid_chain(&aggregate_id).await.unwrap());
+
+        // A purge no checkpoint describes leaves a dangling parent
+        store.purge(&aggregate_id, kept[1].sequence).await.unwrap();
+        assert!(!store.validate_cid_chain(&aggregate_id).await.unwrap());
+    }
+
+    pub async fn rejects_appends_to_tombstoned_aggregates(store: Arc<dyn EventStore>) {
+        let aggregate_id = Uuid::new_v4().to_string();
+        append_chain(&*store, &aggregate_id, 1).await;
+
+        let closed = tombstone(&*store, &aggregate_id, "done").await.unwrap();
+        let result = store
+            .append_event(&aggregate_id, test_event(&aggregate_id, "too late"), closed.cid)
+            .await;
+
+        assert!(matches!(result, Err(EventStoreError::AggregateClosed(_))));
+        assert!(store.validate_cid_chain(&aggregate_id).await.unwrap());
+    }
+}
+
+/// Generate one test per check for a backend
+macro_rules! conformance_tests {
+    (@check [$(#[$attr:meta])*] $store:expr; $check:ident) => {
+        #[tokio::test]
+        $(#[$attr])*
+        async fn $check() {
+            let store: Arc<dyn EventStore> = Arc::new($store);
+            checks::$check(store).await;
+        }
+    };
+    (@checks $attrs:tt $store:expr; $($check:ident),*) => {
+        $(conformance_tests!(@check $attrs $store; $check);)*
+    };
+    ($store:expr $(, #[$attr:meta])*) => {
+        conformance_tests!(@checks [$(#[$attr])*] $store;
+            appends_and_retrieves_events,
+            links_events_into_a_cid_chain,
+            rejects_a_stale_parent,
+            keeps_aggregate_ids_unique_across_types,
+            tracks_correlation_and_causation,
+            replays_from_a_sequence,
+            reads_backward_newest_first,
+            stores_repeated_message_ids_once,
+            delivers_history_then_live_events,
+            assigns_unique_sequences_to_concurrent_appends,
+            lists_aggregates_with_their_heads,
+            keeps_truncated_chains_verifiable,
+            rejects_appends_to_tombstoned_aggregates
+        );
+    };
+}
+
+mod in_memory {
+    use super::*;
+
+    conformance_tests!(InMemoryEventStore::new());
+}
+
+mod file {
+    use super::*;
+
+    conformance_tests!(file_store().await);
+}
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+
+    conformance_tests!(sqlite_store().await);
+}
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use super::*;
+
+    conformance_tests!(postgres_store().await, #[ignore = "requires PostgreSQL at DATABASE_URL"]);
+}
+
+mod jetstream {
+    use super::*;
+
+    conformance_tests!(jetstream_store().await, #[ignore = "requires a NATS server on localhost:4222"]);
+}
@@ -1,10 +1,10 @@
 #[cfg(test)]
 mod event_store_tests {
-    use super::*;
-    use async_nats::jetstream::{self, stream::Config as StreamConfig};
-    use cim_events::event_store::{EventStore, JetStreamEventStore, StoredEvent, EventMetadata};
+    use async_nats::jetstream;
+    use cim_events::event_store::{EventStore, EventStoreError, EventStoreExt, JetStreamEventStore};
     use cim_events::domain::{Event, EventHeader};
     use cid::Cid;
+    use futures::StreamExt;
     use serde::{Deserialize, Serialize};
     use uuid::Uuid;
 
@@ -25,6 +25,7 @@ mod event_store_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
     async fn event_store_should_append_and_retrieve_events() {
         // Given
         let client = async_nats::connect("nats://localhost:4222").await.unwrap();
@@ -54,6 +55,7 @@ mod event_store_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
     async fn event_store_should_maintain_cid_chain() {
         // Given
         let client = async_nats::connect("nats://localhost:4222").await.unwrap();
@@ -73,7 +75,7 @@ mod event_store_tests {
             id: aggregate_id.clone(),
             data: "event 2".to_string(),
         };
-        let metadata2 = store.append_event(&aggregate_id, event2, Some(metadata1.cid.clone().unwrap())).await.unwrap();
+        let metadata2 = store.append_event(&aggregate_id, event2, Some(metadata1.cid.unwrap())).await.unwrap();
         
         // Then - verify CID chain
         assert!(metadata1.cid.is_some());
@@ -85,10 +87,11 @@ mod event_store_tests {
         assert_eq!(events.len(), 2);
         
         assert!(events[0].parent_cid.is_none());
-        assert_eq!(events[1].parent_cid, metadata1.cid);
+        assert_eq!(events[1].parent_cid, metadata1.cid.map(|cid| cid.to_string()));
     }
 
     #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
     async fn event_store_should_handle_correlation_and_causation() {
         // Given
         let client = async_nats::connect("nats://localhost:4222").await.unwrap();
@@ -112,7 +115,7 @@ mod event_store_tests {
         };
         
         // When
-        let metadata = store.append_event_with_header(&aggregate_id, event, header, None).await.unwrap();
+        store.append_event_with_header(&aggregate_id, event, header, None).await.unwrap();
         
         // Then
         let events = store.get_events(&aggregate_id, 0, 10).await.unwrap();
@@ -124,6 +127,7 @@ mod event_store_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
     async fn event_store_should_support_event_replay_from_sequence() {
         // Given
         let client = async_nats::connect("nats://localhost:4222").await.unwrap();
@@ -152,6 +156,7 @@ mod event_store_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
     async fn event_store_should_validate_cid_chain_integrity() {
         // Given
         let client = async_nats::connect("nats://localhost:4222").await.unwrap();
@@ -175,6 +180,7 @@ mod event_store_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
     async fn event_store_should_support_stream_subscriptions() {
         // Given
         let client = async_nats::connect("nats://localhost:4222").await.unwrap();
@@ -200,6 +206,7 @@ mod event_store_tests {
     }
 
     #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
     async fn event_store_should_handle_concurrent_appends() {
         // Given
         let client = async_nats::connect("nats://localhost:4222").await.unwrap();
@@ -217,7 +224,7 @@ mod event_store_tests {
             
             let handle = tokio::spawn(async move {
                 let event = TestEvent {
-                    id: aggregate_id_clone,
+                    id: aggregate_id_clone.clone(),
                     data: format!("concurrent event {}", i),
                 };
                 store_clone.append_event(&aggregate_id_clone, event, None).await
@@ -240,4 +247,88 @@ mod event_store_tests {
         sequences.sort();
         assert_eq!(sequences, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
+    async fn event_store_should_read_backward_through_a_long_stream() {
+        // Given
+        let client = async_nats::connect("nats://localhost:4222").await.unwrap();
+        let jetstream = jetstream::new(client);
+        let store = JetStreamEventStore::new(jetstream, "test-events").await.unwrap();
+        
+        let aggregate_id = Uuid::new_v4().to_string();
+        let other_id = Uuid::new_v4().to_string();
+        
+        // When - the aggregate's events are spread out between other events,
+        // so backward reads scan several windows
+        let mut parent: Option<Cid> = None;
+        for i in 0..40 {
+            let event = TestEvent {
+                id: aggregate_id.clone(),
+                data: format!("event {}", i),
+            };
+            parent = store.append_event(&aggregate_id, event, parent).await.unwrap().cid;
+            for _ in 0..4 {
+                let noise = TestEvent {
+                    id: other_id.clone(),
+                    data: "noise".to_string(),
+                };
+                store.append_event(&other_id, noise, None).await.unwrap();
+            }
+        }
+        let all = store.get_events(&aggregate_id, 0, 100).await.unwrap();
+        
+        // Then - the head comes back alone, and longer reads are complete
+        let head = store.read_backward(&aggregate_id, None, 1).await.unwrap();
+        assert_eq!(head.len(), 1);
+        assert_eq!(head[0].cid, parent.map(|cid| cid.to_string()));
+        
+        let everything = store.read_backward(&aggregate_id, None, 100).await.unwrap();
+        let mut sequences: Vec<u64> = everything.iter().map(|e| e.sequence).collect();
+        sequences.reverse();
+        assert_eq!(sequences, all.iter().map(|e| e.sequence).collect::<Vec<_>>());
+        
+        let middle = store.read_backward(&aggregate_id, Some(all[20].sequence), 3).await.unwrap();
+        let sequences: Vec<u64> = middle.iter().map(|e| e.sequence).collect();
+        assert_eq!(sequences, vec![all[20].sequence, all[19].sequence, all[18].sequence]);
+    }
+
+    #[tokio::test]
+    #[ignore = "requires a NATS server on localhost:4222"]
+    async fn event_store_should_read_between_event_timestamps() {
+        use cim_events::EventScope;
+        use futures::TryStreamExt;
+
+        // Given
+        let client = async_nats::connect("nats://localhost:4222").await.unwrap();
+        let jetstream = jetstream::new(client);
+        let store = JetStreamEventStore::new(jetstream, "test-events").await.unwrap();
+        
+        let aggregate_id = Uuid::new_v4().to_string();
+        let mut parent: Option<Cid> = None;
+        for i in 0..3 {
+            let event = TestEvent {
+                id: aggregate_id.clone(),
+                data: format!("event {}", i),
+            };
+            parent = store.append_event(&aggregate_id, event, parent).await.unwrap().cid;
+        }
+        let events = store.get_events(&aggregate_id, 0, 10).await.unwrap();
+        
+        // When - the window is bounded by the middle event's own timestamp,
+        // which is earlier than the time the server stored it
+        let from = events[1].timestamp;
+        let to = events[1].timestamp + chrono::Duration::nanoseconds(1);
+        let window: Vec<_> = store
+            .read_between(EventScope::Aggregate(aggregate_id.clone()), from, to)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        
+        // Then
+        assert_eq!(window.len(), 1);
+        assert_eq!(window[0].sequence, events[1].sequence);
+    }
+}
@@ -407,8 +407,10 @@ fn create_stored_event<E: Event + Serialize>(event: E) -> StoredEvent {
     StoredEvent {
         sequence: 0,
         aggregate_id: event.aggregate_id().to_string(),
+        aggregate_type: Some(event.aggregate_type().to_string()),
         event_type: event.event_type().to_string(),
         event_data: serde_json::to_value(event).unwrap(),
+        payload_cid: None,
         header: EventHeader::new(),
         cid: None,
         parent_cid: None,
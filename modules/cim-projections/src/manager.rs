@@ -1,5 +1,5 @@
 use async_trait::async_trait;
-use cim_events::{EventStore, StoredEvent};
+use cim_events::{AggregateQuery, EventScope, EventStore, StoredEvent, SubscribeFrom};
 use futures::stream::StreamExt;
 use std::sync::Arc;
 use tokio::sync::RwLock;
@@ -83,20 +83,20 @@ pub trait DynamicProjectionStore: Send + Sync {
 }
 
 /// Runs projections by subscribing to event streams
-pub struct ProjectionRunner<E: EventStore> {
-    event_store: Arc<E>,
+pub struct ProjectionRunner {
+    event_store: Arc<dyn EventStore>,
     manager: ProjectionManager,
     store: Arc<dyn DynamicProjectionStore>,
 }
 
-impl<E: EventStore> ProjectionRunner<E> {
+impl ProjectionRunner {
     pub fn new(
-        event_store: E,
+        event_store: Arc<dyn EventStore>,
         manager: ProjectionManager,
         store: Arc<dyn DynamicProjectionStore>,
     ) -> Self {
         Self {
-            event_store: Arc::new(event_store),
+            event_store,
             manager,
             store,
         }
@@ -114,10 +114,14 @@ impl<E: EventStore> ProjectionRunner<E> {
         // Find the minimum position to start from
         let min_position = positions.iter().map(|(_, p)| *p).min().unwrap_or(0);
         
-        // Subscribe to events from the minimum position
+        // Resume after the last processed event; history and live events
+        // arrive on one subscription with no gap or duplicate
         let mut subscription = self
             .event_store
-            .subscribe_to_events(aggregate_id)
+            .subscribe_with(
+                EventScope::Aggregate(aggregate_id.to_string()),
+                SubscribeFrom::Sequence(min_position + 1),
+            )
             .await
             .map_err(|e| ProjectionError::EventProcessing(e.to_string()))?;
         
@@ -129,11 +133,6 @@ impl<E: EventStore> ProjectionRunner<E> {
         
         // Process events
         while let Some(event) = subscription.next().await {
-            // Skip events we've already processed
-            if event.sequence <= min_position {
-                continue;
-            }
-            
             // Handle the event
             self.manager.handle_event(&event, self.store.as_ref()).await?;
         }
@@ -175,6 +174,31 @@ impl<E: EventStore> ProjectionRunner<E> {
         
         Ok(())
     }
+    
+    /// Run catch-up for every aggregate in the store, or every aggregate of
+    /// one type, listing them page by page
+    pub async fn run_catch_up_all(&self, aggregate_type: Option<&str>) -> Result<()> {
+        let mut query = AggregateQuery::new();
+        if let Some(aggregate_type) = aggregate_type {
+            query = query.with_type(aggregate_type);
+        }
+        
+        loop {
+            let page = self
+                .event_store
+                .list_aggregates(query.clone())
+                .await
+                .map_err(|e| ProjectionError::EventProcessing(e.to_string()))?;
+            
+            self.run_catch_up(page.aggregates.into_iter().map(|info| info.aggregate_id).collect())
+                .await?;
+            
+            match page.next {
+                Some(cursor) => query = query.after(&cursor),
+                None => return Ok(()),
+            }
+        }
+    }
 }
 
 // Implementation helpers for concrete types
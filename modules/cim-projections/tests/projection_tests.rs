@@ -137,12 +137,14 @@ mod projection_tests {
         let event = StoredEvent {
             sequence: 1,
             aggregate_id: "prod-123".to_string(),
+            aggregate_type: Some("Product".to_string()),
             event_type: "ProductCreated".to_string(),
             event_data: serde_json::to_value(ProductCreated {
                 product_id: "prod-123".to_string(),
                 name: "Widget".to_string(),
                 price: 19.99,
             }).unwrap(),
+            payload_cid: None,
             header: EventHeader::new(),
             cid: None,
             parent_cid: None,
@@ -170,12 +172,14 @@ mod projection_tests {
         let create_event = StoredEvent {
             sequence: 1,
             aggregate_id: "prod-123".to_string(),
+            aggregate_type: Some("Product".to_string()),
             event_type: "ProductCreated".to_string(),
             event_data: serde_json::to_value(ProductCreated {
                 product_id: "prod-123".to_string(),
                 name: "Widget".to_string(),
                 price: 19.99,
             }).unwrap(),
+            payload_cid: None,
             header: EventHeader::new(),
             cid: None,
             parent_cid: None,
@@ -187,12 +191,14 @@ mod projection_tests {
         let price_change_event = StoredEvent {
             sequence: 2,
             aggregate_id: "prod-123".to_string(),
+            aggregate_type: Some("Product".to_string()),
             event_type: "ProductPriceChanged".to_string(),
             event_data: serde_json::to_value(ProductPriceChanged {
                 product_id: "prod-123".to_string(),
                 old_price: 19.99,
                 new_price: 24.99,
             }).unwrap(),
+            payload_cid: None,
             header: EventHeader::new(),
             cid: None,
             parent_cid: None,
@@ -225,10 +231,12 @@ mod projection_tests {
         let delete_event = StoredEvent {
             sequence: 3,
             aggregate_id: "prod-123".to_string(),
+            aggregate_type: Some("Product".to_string()),
             event_type: "ProductDeleted".to_string(),
             event_data: serde_json::to_value(ProductDeleted {
                 product_id: "prod-123".to_string(),
             }).unwrap(),
+            payload_cid: None,
             header: EventHeader::new(),
             cid: None,
             parent_cid: None,
@@ -251,12 +259,14 @@ mod projection_tests {
             StoredEvent {
                 sequence: 1,
                 aggregate_id: "prod-1".to_string(),
+                aggregate_type: Some("Product".to_string()),
                 event_type: "ProductCreated".to_string(),
                 event_data: serde_json::to_value(ProductCreated {
                     product_id: "prod-1".to_string(),
                     name: "Widget A".to_string(),
                     price: 10.00,
                 }).unwrap(),
+                payload_cid: None,
                 header: EventHeader::new(),
                 cid: None,
                 parent_cid: None,
@@ -265,12 +275,14 @@ mod projection_tests {
             StoredEvent {
                 sequence: 2,
                 aggregate_id: "prod-2".to_string(),
+                aggregate_type: Some("Product".to_string()),
                 event_type: "ProductCreated".to_string(),
                 event_data: serde_json::to_value(ProductCreated {
                     product_id: "prod-2".to_string(),
                     name: "Widget B".to_string(),
                     price: 20.00,
                 }).unwrap(),
+                payload_cid: None,
                 header: EventHeader::new(),
                 cid: None,
                 parent_cid: None,
@@ -307,12 +319,14 @@ mod projection_tests {
                 let event = StoredEvent {
                     sequence: i,
                     aggregate_id: format!("prod-{}", i),
+                    aggregate_type: Some("Product".to_string()),
                     event_type: "ProductCreated".to_string(),
                     event_data: serde_json::to_value(ProductCreated {
                         product_id: format!("prod-{}", i),
                         name: format!("Widget {}", i),
                         price: 10.0 * i as f64,
                     }).unwrap(),
+                    payload_cid: None,
                     header: EventHeader::new(),
                     cid: None,
                     parent_cid: None,